@@ -0,0 +1,41 @@
+//! Pre-rasterize a character set into a glyph atlas file
+//!
+//! Usage: cargo run --example bake_atlas -- <font.ttf> <chars.txt> <out.atlas> <scale>...
+//! e.g. bake JIS level 1 kanji and kana listed in chars.txt at 12 and 16 dots:
+//!     cargo run --example bake_atlas -- assets/migu-1m-regular.ttf chars.txt jis1.atlas 12 16
+
+extern crate altena_core;
+
+use altena_core::{FontHandle, GlyphAtlas};
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() < 4 {
+        eprintln!("Usage: bake_atlas <font.ttf> <chars.txt> <out.atlas> <scale>...");
+        process::exit(1);
+    }
+    let font_data = fs::read(&args[0]).expect("failed to read the font");
+    let chars = fs::read_to_string(&args[1]).expect("failed to read the character set");
+    let scales: Vec<u8> = args[3..]
+        .iter()
+        .map(|s| match s.parse() {
+            Ok(scale) if scale > 0 => scale,
+            _ => {
+                eprintln!("scale must be in 1..256: {}", s);
+                process::exit(1);
+            }
+        })
+        .collect();
+    let mut chars: Vec<char> = chars.chars().filter(|c| !c.is_control()).collect();
+    chars.sort();
+    chars.dedup();
+    let mut font = FontHandle::new(&font_data);
+    let atlas = GlyphAtlas::bake(&mut font, chars.iter().cloned(), &scales);
+    atlas.save(Path::new(&args[2])).expect("failed to save the atlas");
+    let missing = chars.len() * scales.len() - atlas.len();
+    println!("baked {} glyphs({} missing in the font)", atlas.len(), missing);
+}
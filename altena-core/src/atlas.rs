@@ -0,0 +1,365 @@
+//! pre-rasterized glyphs, loadable without the original font
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use std::cmp;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use binary::{invalid, read_header, write_header, BinResult};
+use font::{FontCache, TextFont};
+use text::{FontMetrics, LineMetrics};
+
+const ATLAS_MAGIC: &[u8; 4] = b"ALTA";
+/// minimum width of the page
+const PAGE_WIDTH: usize = 256;
+/// the largest page we load(32MB)
+const MAX_PAGE_DOTS: usize = 1 << 26;
+
+/// Place of a glyph in the page
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct AtlasGlyph {
+    x: u16,
+    y: u16,
+    width: u16,
+    height: u16,
+    bearing: (i32, i32),
+    advance: f32,
+}
+
+impl AtlasGlyph {
+    /// Copy the glyph out of `page`
+    fn cut(&self, page: &FontCache) -> FontCache {
+        let (w, h) = (usize::from(self.width), usize::from(self.height));
+        let mut bitmap = FontCache::new(w, h, self.bearing);
+        let (x, y) = (u32::from(self.x), u32::from(self.y));
+        bitmap.points().for_each(|(dx, dy)| {
+            bitmap.set(dx, dy, page.get(x + dx, y + dy));
+        });
+        bitmap
+    }
+}
+
+/// Glyphs of a fixed character set rasterized at fixed scales, packed into one
+/// 4bit alpha page.
+/// Drawing from an atlas never rasterizes, so the first frame showing a new
+/// character is as fast as the others. Kerning isn't stored.
+#[derive(Clone, Debug)]
+pub struct GlyphAtlas {
+    /// all glyphs, with the same packing as FontCache
+    page: FontCache,
+    glyphs: HashMap<(char, u8), AtlasGlyph>,
+    metrics: HashMap<u8, LineMetrics>,
+    /// glyphs cut out of the page, on first use
+    cache: HashMap<(char, u8), FontCache>,
+}
+
+impl Default for GlyphAtlas {
+    fn default() -> GlyphAtlas {
+        GlyphAtlas {
+            page: FontCache::new(0, 0, (0, 0)),
+            glyphs: HashMap::new(),
+            metrics: HashMap::new(),
+            cache: HashMap::new(),
+        }
+    }
+}
+
+impl GlyphAtlas {
+    /// Rasterize `chars` at each of `scales`.
+    /// Characters `font` doesn't have are skipped.
+    pub fn bake<F, I>(font: &mut F, chars: I, scales: &[u8]) -> GlyphAtlas
+    where
+        F: TextFont + ?Sized,
+        I: IntoIterator<Item = char>,
+    {
+        let chars: Vec<char> = chars.into_iter().collect();
+        let mut atlas = GlyphAtlas::default();
+        let mut bitmaps = Vec::new();
+        for &scale in scales {
+            let scale_f = f32::from(scale);
+            atlas.metrics.insert(scale, font.line_metrics(scale_f));
+            for &c in &chars {
+                let advance = match font.advance(c, scale_f) {
+                    Some(a) => a,
+                    None => continue,
+                };
+                if let Some(bitmap) = font.glyph(c, scale) {
+                    bitmaps.push(((c, scale), bitmap.clone(), advance));
+                }
+            }
+        }
+        atlas.pack(bitmaps);
+        atlas
+    }
+    /// Place bitmaps on shelves, from the tallest one
+    fn pack(&mut self, mut bitmaps: Vec<((char, u8), FontCache, f32)>) {
+        bitmaps.sort_by(|a, b| (b.1.height(), a.0).cmp(&(a.1.height(), b.0)));
+        // roughly square, so that both sides fit in u16
+        let area: usize = bitmaps.iter().map(|b| b.1.width() * b.1.height()).sum();
+        let page_width = bitmaps.iter().fold(
+            cmp::max(PAGE_WIDTH, (area as f64).sqrt() as usize),
+            |acc, b| cmp::max(acc, b.1.width()),
+        );
+        let (mut x, mut y, mut shelf) = (0, 0, 0);
+        for &(key, ref bitmap, advance) in &bitmaps {
+            if x + bitmap.width() > page_width {
+                x = 0;
+                y += shelf;
+                shelf = 0;
+            }
+            if shelf == 0 {
+                shelf = bitmap.height();
+            }
+            let glyph = AtlasGlyph {
+                x: x as u16,
+                y: y as u16,
+                width: bitmap.width() as u16,
+                height: bitmap.height() as u16,
+                bearing: bitmap.bearing(),
+                advance: advance,
+            };
+            self.glyphs.insert(key, glyph);
+            x += bitmap.width();
+        }
+        self.page = FontCache::new(page_width, y + shelf, (0, 0));
+        for (key, bitmap, _) in bitmaps {
+            let (x, y) = {
+                let g = &self.glyphs[&key];
+                (u32::from(g.x), u32::from(g.y))
+            };
+            for (dx, dy) in bitmap.points() {
+                self.page.set(x + dx, y + dy, bitmap.get(dx, dy));
+            }
+        }
+    }
+    /// Number of glyphs(for all scales)
+    pub fn len(&self) -> usize {
+        self.glyphs.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.glyphs.is_empty()
+    }
+    pub fn scales(&self) -> Vec<u8> {
+        let mut res: Vec<_> = self.metrics.keys().cloned().collect();
+        res.sort();
+        res
+    }
+    pub fn contains(&self, c: char, scale: u8) -> bool {
+        self.glyphs.contains_key(&(c, scale))
+    }
+    /// Size of the page in dots
+    pub fn page_size(&self) -> (usize, usize) {
+        (self.page.width(), self.page.height())
+    }
+    pub fn save(&self, path: &Path) -> BinResult<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write_binary(&mut w)
+    }
+    pub fn load(path: &Path) -> BinResult<GlyphAtlas> {
+        let mut r = BufReader::new(File::open(path)?);
+        GlyphAtlas::read_binary(&mut r)
+    }
+    pub fn write_binary<W: Write>(&self, w: &mut W) -> BinResult<()> {
+        if !page_fits(self.page.width(), self.page.height()) {
+            return invalid("page too large");
+        }
+        write_header(w, ATLAS_MAGIC)?;
+        w.write_u16::<LittleEndian>(self.metrics.len() as u16)?;
+        for scale in self.scales() {
+            let m = self.metrics[&scale];
+            w.write_u8(scale)?;
+            w.write_f32::<LittleEndian>(m.ascent)?;
+            w.write_f32::<LittleEndian>(m.descent)?;
+            w.write_f32::<LittleEndian>(m.line_gap)?;
+        }
+        w.write_u16::<LittleEndian>(self.page.width() as u16)?;
+        w.write_u16::<LittleEndian>(self.page.height() as u16)?;
+        w.write_all(self.page.packed())?;
+        // sorted, so that the same atlas is always written the same
+        let mut keys: Vec<_> = self.glyphs.keys().cloned().collect();
+        keys.sort();
+        w.write_u32::<LittleEndian>(keys.len() as u32)?;
+        for key in keys {
+            let glyph = &self.glyphs[&key];
+            w.write_u32::<LittleEndian>(key.0 as u32)?;
+            w.write_u8(key.1)?;
+            w.write_f32::<LittleEndian>(glyph.advance)?;
+            w.write_i32::<LittleEndian>(glyph.bearing.0)?;
+            w.write_i32::<LittleEndian>(glyph.bearing.1)?;
+            w.write_u16::<LittleEndian>(glyph.x)?;
+            w.write_u16::<LittleEndian>(glyph.y)?;
+            w.write_u16::<LittleEndian>(glyph.width)?;
+            w.write_u16::<LittleEndian>(glyph.height)?;
+        }
+        Ok(())
+    }
+    pub fn read_binary<R: Read>(r: &mut R) -> BinResult<GlyphAtlas> {
+        read_header(r, ATLAS_MAGIC)?;
+        let mut atlas = GlyphAtlas::default();
+        for _ in 0..r.read_u16::<LittleEndian>()? {
+            let scale = r.read_u8()?;
+            let metrics = LineMetrics {
+                ascent: r.read_f32::<LittleEndian>()?,
+                descent: r.read_f32::<LittleEndian>()?,
+                line_gap: r.read_f32::<LittleEndian>()?,
+            };
+            atlas.metrics.insert(scale, metrics);
+        }
+        let width = r.read_u16::<LittleEndian>()? as usize;
+        let height = r.read_u16::<LittleEndian>()? as usize;
+        if !page_fits(width, height) {
+            return invalid("page too large");
+        }
+        let mut packed = vec![0u8; (width * height + 1) / 2];
+        r.read_exact(&mut packed)?;
+        atlas.page = match FontCache::from_packed(width, height, (0, 0), packed) {
+            Some(page) => page,
+            None => return invalid("broken page"),
+        };
+        for _ in 0..r.read_u32::<LittleEndian>()? {
+            let c = match ::std::char::from_u32(r.read_u32::<LittleEndian>()?) {
+                Some(c) => c,
+                None => return invalid("invalid char"),
+            };
+            let scale = r.read_u8()?;
+            if !atlas.metrics.contains_key(&scale) {
+                return invalid("glyph of unknown scale");
+            }
+            let glyph = AtlasGlyph {
+                advance: r.read_f32::<LittleEndian>()?,
+                bearing: (r.read_i32::<LittleEndian>()?, r.read_i32::<LittleEndian>()?),
+                x: r.read_u16::<LittleEndian>()?,
+                y: r.read_u16::<LittleEndian>()?,
+                width: r.read_u16::<LittleEndian>()?,
+                height: r.read_u16::<LittleEndian>()?,
+            };
+            let right = usize::from(glyph.x) + usize::from(glyph.width);
+            let bottom = usize::from(glyph.y) + usize::from(glyph.height);
+            if right > width || bottom > height {
+                return invalid("glyph out of the page");
+            }
+            atlas.glyphs.insert((c, scale), glyph);
+        }
+        Ok(atlas)
+    }
+}
+
+fn page_fits(width: usize, height: usize) -> bool {
+    let max = usize::from(u16::max_value());
+    width <= max && height <= max && width * height <= MAX_PAGE_DOTS
+}
+
+impl FontMetrics for GlyphAtlas {
+    fn advance(&self, c: char, scale: f32) -> Option<f32> {
+        self.glyphs.get(&(c, scale as u8)).map(|g| g.advance)
+    }
+    fn kerning(&self, _a: char, _b: char, _scale: f32) -> f32 {
+        0.0
+    }
+    fn line_metrics(&self, scale: f32) -> LineMetrics {
+        match self.metrics.get(&(scale as u8)) {
+            Some(m) => *m,
+            None => LineMetrics {
+                ascent: scale,
+                descent: 0.0,
+                line_gap: 0.0,
+            },
+        }
+    }
+}
+
+impl TextFont for GlyphAtlas {
+    fn glyph(&mut self, c: char, scale: u8) -> Option<&FontCache> {
+        let page = &self.page;
+        let cache = match self.cache.entry((c, scale)) {
+            Entry::Occupied(o_e) => o_e.into_mut(),
+            Entry::Vacant(v_e) => v_e.insert(self.glyphs.get(&(c, scale))?.cut(page)),
+        };
+        Some(cache)
+    }
+}
+
+#[cfg(test)]
+mod atlas_test {
+    use super::*;
+    use binary::BinaryError;
+    use font::{FontHandle, FontSetting, TextDraw};
+    use image::{Rgba, RgbaImage};
+    use std::io::Cursor;
+    use test::Bencher;
+    const MIGU: &[u8; 3137552] = include_bytes!("../../assets/migu-1m-regular.ttf");
+    const CHARS: &str = "あいうえお隣の客はよく柿食うだABCabc,.";
+    #[test]
+    fn same_as_font() {
+        let mut font = FontHandle::new(&MIGU[..]);
+        let atlas = GlyphAtlas::bake(&mut font, CHARS.chars(), &[12, 16]);
+        assert_eq!(atlas.len(), CHARS.chars().count() * 2);
+        let mut buf = Vec::new();
+        atlas.write_binary(&mut buf).unwrap();
+        let mut atlas = GlyphAtlas::read_binary(&mut Cursor::new(&buf)).unwrap();
+        let white = Rgba { data: [255; 4] };
+        for &scale in &[12, 16] {
+            let mut setting = FontSetting::new();
+            setting.scale(scale).max_width(100).start((1.0, 2.0));
+            let mut expected = RgbaImage::from_pixel(128, 128, white);
+            let mut img = RgbaImage::from_pixel(128, 128, white);
+            let bbox1 = font.draw_str(&mut expected, CHARS, &setting).unwrap();
+            let bbox2 = atlas.draw_str(&mut img, CHARS, &setting).unwrap();
+            // atlas doesn't have kerning, but Migu is monospace
+            assert_eq!(bbox1, bbox2);
+            assert_eq!(expected.into_raw(), img.into_raw());
+        }
+        assert!(!atlas.contains('か', 16));
+    }
+    #[test]
+    fn broken() {
+        let mut buf = Vec::new();
+        GlyphAtlas::default().write_binary(&mut buf).unwrap();
+        buf[0] = b'X';
+        match GlyphAtlas::read_binary(&mut Cursor::new(&buf)) {
+            Err(BinaryError::Magic) => {}
+            r => panic!("{:?}", r),
+        }
+    }
+    #[test]
+    fn malformed() {
+        let mut buf = Vec::new();
+        write_header(&mut buf, ATLAS_MAGIC).unwrap();
+        buf.write_u16::<LittleEndian>(0).unwrap();
+        // a 65535x65535 page
+        buf.write_u16::<LittleEndian>(0xffff).unwrap();
+        buf.write_u16::<LittleEndian>(0xffff).unwrap();
+        match GlyphAtlas::read_binary(&mut Cursor::new(&buf)) {
+            Err(BinaryError::Invalid(_)) => {}
+            r => panic!("{:?}", r),
+        }
+        let mut font = FontHandle::new(&MIGU[..]);
+        let atlas = GlyphAtlas::bake(&mut font, "あ".chars(), &[16]);
+        assert_eq!(atlas.page_size().0, PAGE_WIDTH);
+        let mut buf = Vec::new();
+        atlas.write_binary(&mut buf).unwrap();
+        // move the glyph out of the page
+        let x_pos = buf.len() - 8;
+        buf[x_pos + 1] = 0xff;
+        match GlyphAtlas::read_binary(&mut Cursor::new(&buf)) {
+            Err(BinaryError::Invalid(_)) => {}
+            r => panic!("{:?}", r),
+        }
+    }
+    #[bench]
+    fn atlas(b: &mut Bencher) {
+        let mut font = FontHandle::new(&MIGU[..]);
+        let mut atlas = GlyphAtlas::bake(&mut font, "あ".chars(), &[16]);
+        let setting = FontSetting::new();
+        let mut img = RgbaImage::from_pixel(16, 16, Rgba { data: [255; 4] });
+        b.iter(|| {
+            (0..100).for_each(|_| {
+                atlas.draw(&mut img, 'あ', &setting).unwrap();
+            });
+        });
+    }
+}
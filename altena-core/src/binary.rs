@@ -0,0 +1,395 @@
+//! versioned binary format for Frame and MeshTree
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use euclid::{point2, size2};
+use rect_iter::{Get2D, GetMut2D};
+
+use std::cmp;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use frame::dottypes::*;
+use frame::{tile_num, Frame, MeshLeaf, MeshNode, MeshTree, TileDir};
+use tile::tiletypes::*;
+use tile::{Alpha, Color, Tile};
+
+const FRAME_MAGIC: &[u8; 4] = b"ALTF";
+const MESH_MAGIC: &[u8; 4] = b"ALTM";
+/// Version of the format. Bump this when the layout changes.
+pub const FORMAT_VERSION: u16 = 1;
+
+const LEAF_TAG: u8 = 0;
+const NODE_TAG: u8 = 1;
+/// scale of the largest mesh(32768 dots)
+const MAX_SCALE: i16 = 2048;
+/// bytes of the bitmap which flags non-transparent dots in a tile
+const DOT_MASK_LEN: usize = TILE_SIZE * TILE_SIZE / 8;
+
+#[derive(Debug)]
+pub enum BinaryError {
+    Io(io::Error),
+    /// the data doesn't start with the magic number
+    Magic,
+    /// the data is written in an unsupported version
+    Version(u16),
+    Invalid(&'static str),
+}
+
+impl Error for BinaryError {
+    fn description(&self) -> &str {
+        "Binary Error"
+    }
+}
+
+impl fmt::Display for BinaryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            BinaryError::Io(e) => write!(f, "Io: {}", e),
+            BinaryError::Magic => write!(f, "Magic"),
+            BinaryError::Version(v) => write!(f, "Version: {}", v),
+            BinaryError::Invalid(s) => write!(f, "Invalid: {}", s),
+        }
+    }
+}
+
+impl From<io::Error> for BinaryError {
+    fn from(e: io::Error) -> BinaryError {
+        BinaryError::Io(e)
+    }
+}
+
+pub(crate) type BinResult<T> = Result<T, BinaryError>;
+
+pub(crate) fn invalid<T>(s: &'static str) -> BinResult<T> {
+    Err(BinaryError::Invalid(s))
+}
+
+pub(crate) fn write_header<W: Write>(w: &mut W, magic: &[u8; 4]) -> io::Result<()> {
+    w.write_all(magic)?;
+    w.write_u16::<LittleEndian>(FORMAT_VERSION)
+}
+
+pub(crate) fn read_header<R: Read>(r: &mut R, magic: &[u8; 4]) -> BinResult<()> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    if &buf != magic {
+        return Err(BinaryError::Magic);
+    }
+    let version = r.read_u16::<LittleEndian>()?;
+    if version != FORMAT_VERSION {
+        return Err(BinaryError::Version(version));
+    }
+    Ok(())
+}
+
+fn write_rect<W: Write>(w: &mut W, rect: DotRect) -> io::Result<()> {
+    w.write_i16::<LittleEndian>(rect.origin.x)?;
+    w.write_i16::<LittleEndian>(rect.origin.y)?;
+    w.write_i16::<LittleEndian>(rect.size.width)?;
+    w.write_i16::<LittleEndian>(rect.size.height)
+}
+
+fn read_rect<R: Read>(r: &mut R) -> io::Result<DotRect> {
+    let x = r.read_i16::<LittleEndian>()?;
+    let y = r.read_i16::<LittleEndian>()?;
+    let w = r.read_i16::<LittleEndian>()?;
+    let h = r.read_i16::<LittleEndian>()?;
+    Ok(DotRect::new(point2(x, y), size2(w, h)))
+}
+
+/// Check that `bbox` is in the square of `len` dots, and its far corner fits in i16
+fn check_bbox(bbox: DotRect, len: i32) -> BinResult<DotRect> {
+    let len = cmp::min(len, i32::from(i16::max_value()));
+    let in_range = |start: i16, l: i16| {
+        let (start, l) = (i32::from(start), i32::from(l));
+        start >= 0 && l > 0 && start + l <= len
+    };
+    if !in_range(bbox.origin.x, bbox.size.width) || !in_range(bbox.origin.y, bbox.size.height) {
+        return invalid("bbox out of range");
+    }
+    Ok(bbox)
+}
+
+fn dir_to_u8(dir: TileDir) -> u8 {
+    TileDir::variants().position(|&d| d == dir).unwrap() as u8
+}
+
+fn write_mesh<W: Write>(w: &mut W, mesh: &MeshTree) -> io::Result<()> {
+    match mesh {
+        MeshTree::Leaf(leaf) => {
+            w.write_u8(LEAF_TAG)?;
+            write_rect(w, leaf.bbox)?;
+            // rows out of bbox are always 0
+            let rows = leaf.bbox.min_y() as usize..leaf.bbox.max_y() as usize;
+            for row in &leaf.inner[rows] {
+                w.write_u64::<LittleEndian>(*row)?;
+            }
+        }
+        MeshTree::Node(node) => {
+            w.write_u8(NODE_TAG)?;
+            write_rect(w, node.bbox)?;
+            w.write_i16::<LittleEndian>(node.scale)?;
+            w.write_u8(node.inner.len() as u8)?;
+            for (child, dir) in &node.inner {
+                w.write_u8(dir_to_u8(*dir))?;
+                write_mesh(w, child)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Read a subtree. `scale` is the scale expected from the parent(None for the root).
+fn read_mesh<R: Read>(r: &mut R, scale: Option<i16>) -> BinResult<MeshTree> {
+    match r.read_u8()? {
+        LEAF_TAG => {
+            if scale.map_or(false, |s| s != 1) {
+                return invalid("unexpected leaf");
+            }
+            let bbox = check_bbox(read_rect(r)?, TILE_SIZE as i32)?;
+            let mut inner = [0u64; TILE_SIZE];
+            for row in &mut inner[bbox.min_y() as usize..bbox.max_y() as usize] {
+                *row = r.read_u64::<LittleEndian>()?;
+            }
+            Ok(MeshTree::Leaf(MeshLeaf {
+                inner: inner,
+                bbox: bbox,
+            }))
+        }
+        NODE_TAG => {
+            let bbox = read_rect(r)?;
+            let node_scale = r.read_i16::<LittleEndian>()?;
+            let is_pow2 = node_scale >= 2 && node_scale & (node_scale - 1) == 0;
+            if !is_pow2 || node_scale > MAX_SCALE || scale.map_or(false, |s| s != node_scale) {
+                return invalid("wrong scale");
+            }
+            let bbox = check_bbox(bbox, i32::from(node_scale) * TILE_SIZE as i32)?;
+            let num = r.read_u8()?;
+            if num == 0 || num > 4 {
+                return invalid("wrong number of children");
+            }
+            let inner = (0..num)
+                .map(|_| {
+                    let dir = match TileDir::variants().nth(usize::from(r.read_u8()?)) {
+                        Some(d) => *d,
+                        None => return invalid("wrong direction"),
+                    };
+                    let child = read_mesh(r, Some(node_scale / 2))?;
+                    Ok((child, dir))
+                })
+                .collect::<BinResult<Vec<_>>>()?;
+            Ok(MeshTree::Node(MeshNode {
+                inner: inner,
+                bbox: bbox,
+                scale: node_scale,
+            }))
+        }
+        _ => invalid("unknown mesh tag"),
+    }
+}
+
+/// Write the bitmap of non-transparent dots, then their colors
+fn write_tile<W: Write>(w: &mut W, tile: &Tile) -> io::Result<()> {
+    let mut mask = [0u8; DOT_MASK_LEN];
+    let mut colors = Vec::new();
+    for (i, (x, y)) in tile_rect().into_iter().enumerate() {
+        if let Some(Some(c)) = tile.get_xy(x, y) {
+            mask[i / 8] |= 1 << (i % 8);
+            colors.extend_from_slice(&[c.r, c.g, c.b]);
+        }
+    }
+    w.write_all(&mask)?;
+    w.write_all(&colors)
+}
+
+fn read_tile<R: Read>(r: &mut R) -> BinResult<Tile> {
+    let mut mask = [0u8; DOT_MASK_LEN];
+    r.read_exact(&mut mask)?;
+    let mut tile = Tile::default();
+    for (i, (x, y)) in tile_rect().into_iter().enumerate() {
+        if mask[i / 8] & (1 << (i % 8)) == 0 {
+            continue;
+        }
+        let mut rgb = [0u8; 3];
+        r.read_exact(&mut rgb)?;
+        if let Some(d) = tile.get_mut_xy(x, y) {
+            *d = Some(Color {
+                r: rgb[0],
+                g: rgb[1],
+                b: rgb[2],
+            });
+        }
+    }
+    Ok(tile)
+}
+
+impl MeshTree {
+    /// Encode the mesh in the binary format
+    pub fn write_binary<W: Write>(&self, w: &mut W) -> BinResult<()> {
+        write_header(w, MESH_MAGIC)?;
+        write_mesh(w, self)?;
+        Ok(())
+    }
+    /// Decode the mesh written by `write_binary`
+    pub fn read_binary<R: Read>(r: &mut R) -> BinResult<MeshTree> {
+        read_header(r, MESH_MAGIC)?;
+        read_mesh(r, None)
+    }
+}
+
+impl Frame {
+    /// Encode the frame(tiles, alpha, original size and mesh) in the binary format
+    pub fn write_binary<W: Write>(&self, w: &mut W) -> BinResult<()> {
+        write_header(w, FRAME_MAGIC)?;
+        let name = self.name.as_bytes();
+        w.write_u32::<LittleEndian>(name.len() as u32)?;
+        w.write_all(name)?;
+        w.write_u16::<LittleEndian>(self.w_orig as u16)?;
+        w.write_u16::<LittleEndian>(self.h_orig as u16)?;
+        w.write_u8(self.alpha.0)?;
+        w.write_u32::<LittleEndian>(self.tiles.len() as u32)?;
+        for (tile, p) in &self.tiles {
+            w.write_u16::<LittleEndian>(p.x)?;
+            w.write_u16::<LittleEndian>(p.y)?;
+            write_tile(w, tile)?;
+        }
+        write_mesh(w, &self.mesh)?;
+        Ok(())
+    }
+    /// Decode the frame written by `write_binary`
+    pub fn read_binary<R: Read>(r: &mut R) -> BinResult<Frame> {
+        read_header(r, FRAME_MAGIC)?;
+        let name_len = u64::from(r.read_u32::<LittleEndian>()?);
+        let mut name = Vec::new();
+        r.by_ref().take(name_len).read_to_end(&mut name)?;
+        if name.len() as u64 != name_len {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        let name = match String::from_utf8(name) {
+            Ok(s) => s,
+            Err(_) => return invalid("name is not UTF-8"),
+        };
+        let w_orig = usize::from(r.read_u16::<LittleEndian>()?);
+        let h_orig = usize::from(r.read_u16::<LittleEndian>()?);
+        let max_len = i16::max_value() as usize;
+        if w_orig == 0 || h_orig == 0 || w_orig > max_len || h_orig > max_len {
+            return invalid("wrong image size");
+        }
+        let alpha = r.read_u8()?;
+        if alpha > 15 {
+            return invalid("wrong alpha");
+        }
+        let (tw, th) = (tile_num(w_orig), tile_num(h_orig));
+        let tile_len = r.read_u32::<LittleEndian>()? as usize;
+        if tile_len != tw * th {
+            return invalid("wrong number of tiles");
+        }
+        let tiles = (0..tile_len)
+            .map(|_| {
+                let x = r.read_u16::<LittleEndian>()?;
+                let y = r.read_u16::<LittleEndian>()?;
+                if usize::from(x) >= tw || usize::from(y) >= th {
+                    return invalid("tile out of range");
+                }
+                let tile = read_tile(r)?;
+                Ok((tile, point2(x, y)))
+            })
+            .collect::<BinResult<Vec<_>>>()?;
+        let mesh = read_mesh(r, None)?;
+        Ok(Frame {
+            name: name,
+            mesh: mesh,
+            tiles: tiles,
+            alpha: Alpha(alpha),
+            w_orig: w_orig,
+            h_orig: h_orig,
+        })
+    }
+}
+
+#[cfg(test)]
+mod binary_test {
+    use super::*;
+    use testutils::{load_frame, load_mesh};
+    fn roundtrip_frame(path: &str) {
+        let frame = load_frame(path);
+        let mut buf = Vec::new();
+        frame.write_binary(&mut buf).unwrap();
+        let decoded = Frame::read_binary(&mut &buf[..]).unwrap();
+        assert_eq!(decoded.name, frame.name);
+        assert_eq!(decoded.alpha, frame.alpha);
+        assert_eq!(decoded.mesh.bbox(), frame.mesh.bbox());
+        assert_eq!(decoded.restore_buf(), frame.restore_buf());
+        let mut buf2 = Vec::new();
+        decoded.write_binary(&mut buf2).unwrap();
+        assert_eq!(buf, buf2);
+    }
+    #[test]
+    fn frame_roundtrip() {
+        roundtrip_frame("../test-assets/bullet.png");
+        roundtrip_frame("../test-assets/chara1.png");
+        roundtrip_frame("../test-assets/chara2.png");
+    }
+    #[test]
+    fn mesh_roundtrip() {
+        let chara1 = load_mesh("../test-assets/chara1.png");
+        let chara2 = load_mesh("../test-assets/chara2.png");
+        let mut buf = Vec::new();
+        chara2.write_binary(&mut buf).unwrap();
+        let decoded = MeshTree::read_binary(&mut &buf[..]).unwrap();
+        (0..32).for_each(|x| {
+            let p = point2(x, 10);
+            let expected = chara2.collide(&chara1, point2(0, 0), p);
+            assert_eq!(decoded.collide(&chara1, point2(0, 0), p), expected);
+        });
+    }
+    #[test]
+    fn broken_data() {
+        let chara2 = load_mesh("../test-assets/chara2.png");
+        let mut buf = Vec::new();
+        chara2.write_binary(&mut buf).unwrap();
+        match Frame::read_binary(&mut &buf[..]) {
+            Err(BinaryError::Magic) => {}
+            r => panic!("{:?}", r.map(|_| ())),
+        }
+        let mut old = buf.clone();
+        old[4] = 0;
+        match MeshTree::read_binary(&mut &old[..]) {
+            Err(BinaryError::Version(0)) => {}
+            r => panic!("{:?}", r.map(|_| ())),
+        }
+        let truncated = &buf[..buf.len() - 1];
+        match MeshTree::read_binary(&mut &truncated[..]) {
+            Err(BinaryError::Io(_)) => {}
+            r => panic!("{:?}", r.map(|_| ())),
+        }
+    }
+    #[test]
+    fn bbox_out_of_node() {
+        // a node with one leaf, whose bbox is `bbox`
+        let node = |bbox: DotRect, scale: i16| {
+            let mut buf = Vec::new();
+            write_header(&mut buf, MESH_MAGIC).unwrap();
+            buf.push(NODE_TAG);
+            write_rect(&mut buf, bbox).unwrap();
+            buf.write_i16::<LittleEndian>(scale).unwrap();
+            buf.extend_from_slice(&[1, 0, LEAF_TAG]);
+            write_rect(&mut buf, DotRect::new(point2(0, 0), size2(1, 1))).unwrap();
+            buf.write_u64::<LittleEndian>(1).unwrap();
+            buf
+        };
+        let ok = node(DotRect::new(point2(0, 0), size2(32, 1)), 2);
+        assert!(MeshTree::read_binary(&mut &ok[..]).is_ok());
+        // wider than the node of scale 2(32 dots)
+        let wide = node(DotRect::new(point2(0, 0), size2(33, 1)), 2);
+        // the far corner is 32768, which overflows i16
+        let far = node(DotRect::new(point2(32000, 0), size2(768, 1)), MAX_SCALE);
+        for buf in &[wide, far] {
+            match MeshTree::read_binary(&mut &buf[..]) {
+                Err(BinaryError::Invalid(_)) => {}
+                r => panic!("{:?}", r.map(|_| ())),
+            }
+        }
+    }
+}
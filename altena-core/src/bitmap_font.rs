@@ -0,0 +1,361 @@
+//! bitmap fonts(BDF, or PNG grid with a character map)
+
+use image::{self, ImageError, RgbaImage};
+
+use std::cmp;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use font::{FontCache, TextFont};
+use text::{FontMetrics, LineMetrics};
+use tile::Alpha;
+
+#[derive(Debug)]
+pub enum BitmapFontError {
+    Io(io::Error),
+    Image(ImageError),
+    Format(String),
+}
+
+impl Error for BitmapFontError {
+    fn description(&self) -> &str {
+        "BitmapFont Error"
+    }
+}
+
+impl fmt::Display for BitmapFontError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            BitmapFontError::Io(e) => write!(f, "Io: {}", e),
+            BitmapFontError::Image(e) => write!(f, "Image: {}", e),
+            BitmapFontError::Format(s) => write!(f, "Format: {}", s),
+        }
+    }
+}
+
+impl From<io::Error> for BitmapFontError {
+    fn from(e: io::Error) -> BitmapFontError {
+        BitmapFontError::Io(e)
+    }
+}
+
+impl From<ImageError> for BitmapFontError {
+    fn from(e: ImageError) -> BitmapFontError {
+        BitmapFontError::Image(e)
+    }
+}
+
+fn format_err<T>(s: String) -> Result<T, BitmapFontError> {
+    Err(BitmapFontError::Format(s))
+}
+
+#[derive(Clone, Debug)]
+struct BitmapGlyph {
+    bitmap: FontCache,
+    advance: i32,
+}
+
+/// Font made of hand-drawn glyphs, drawn without anti-aliasing.
+/// Glyphs are magnified by an integer factor when `FontSetting::scale` is
+/// larger than the font's height, so 8x8 fonts are drawn exactly at scale 8.
+#[derive(Clone, Debug)]
+pub struct BitmapFont {
+    glyphs: HashMap<char, BitmapGlyph>,
+    ascent: i32,
+    /// dots below the baseline
+    descent: i32,
+    magnified: HashMap<(char, u8), FontCache>,
+}
+
+impl BitmapFont {
+    fn new(ascent: i32, descent: i32) -> BitmapFont {
+        BitmapFont {
+            glyphs: HashMap::new(),
+            ascent: ascent,
+            descent: descent,
+            magnified: HashMap::new(),
+        }
+    }
+    /// Load .bdf file, or PNG grid with the character map in the same
+    /// directory(e.g. font.png and font.txt)
+    pub fn load(path: &Path) -> Result<BitmapFont, BitmapFontError> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("bdf") => BitmapFont::from_bdf(&fs::read_to_string(path)?),
+            _ => {
+                let map = fs::read_to_string(path.with_extension("txt"))?;
+                BitmapFont::from_grid(&image::open(path)?.to_rgba(), &map)
+            }
+        }
+    }
+    /// Parse BDF(Glyph Bitmap Distribution Format)
+    pub fn from_bdf(s: &str) -> Result<BitmapFont, BitmapFontError> {
+        let mut lines = s.lines().map(str::trim);
+        let (mut ascent, mut descent, mut bbox) = (None, None, None);
+        let mut glyphs = Vec::new();
+        while let Some(line) = lines.next() {
+            let mut words = line.split_whitespace();
+            let key = words.next().unwrap_or("");
+            let args = words
+                .map(|w| w.parse::<i32>())
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap_or_default();
+            match key {
+                "FONT_ASCENT" if !args.is_empty() => ascent = Some(args[0]),
+                "FONT_DESCENT" if !args.is_empty() => descent = Some(args[0]),
+                "FONTBOUNDINGBOX" if args.len() == 4 => bbox = Some((args[1], args[3])),
+                "STARTCHAR" => glyphs.push(parse_bdf_char(&mut lines)?),
+                _ => {}
+            }
+        }
+        let (ascent, descent) = match (ascent, descent, bbox) {
+            (Some(a), Some(d), _) => (a, d),
+            (_, _, Some((h, y))) => (h + y, -y),
+            _ => return format_err("no FONT_ASCENT/FONT_DESCENT".to_owned()),
+        };
+        let mut font = BitmapFont::new(ascent, descent);
+        for (c, glyph) in glyphs.into_iter().filter_map(|(c, g)| Some((c?, g))) {
+            font.glyphs.insert(c, glyph);
+        }
+        Ok(font)
+    }
+    /// Load glyphs from a grid of cells.
+    /// The first line of `map` is `<cell width> <cell height> <ascent>`, and each
+    /// following line lists the characters of a row of cells. Opaque dots are ink.
+    /// Lines starting with `#` before the header are comments.
+    pub fn from_grid(buf: &RgbaImage, map: &str) -> Result<BitmapFont, BitmapFontError> {
+        let mut lines = map.lines()
+            .map(|l| l.trim_right_matches('\r'))
+            .skip_while(|l| l.starts_with('#'));
+        let header = lines.next().unwrap_or("");
+        let size = header
+            .split_whitespace()
+            .map(|w| w.parse::<u32>())
+            .collect::<Result<Vec<_>, _>>();
+        let (w, h, ascent) = match size {
+            Ok(ref v) if v.len() == 3 && v[0] > 0 && v[1] > 0 => (v[0], v[1], v[2]),
+            _ => return format_err(format!("wrong header: {}", header)),
+        };
+        let mut font = BitmapFont::new(ascent as i32, h as i32 - ascent as i32);
+        for (row, line) in lines.enumerate() {
+            for (col, c) in line.chars().enumerate() {
+                let (x0, y0) = (col as u32 * w, row as u32 * h);
+                if x0 + w > buf.width() || y0 + h > buf.height() {
+                    return format_err(format!("{} is out of the image", c));
+                }
+                let mut bitmap = FontCache::new(w as usize, h as usize, (0, -(ascent as i32)));
+                bitmap.points().for_each(|(x, y)| {
+                    if buf.get_pixel(x0 + x, y0 + y)[3] >= 128 {
+                        bitmap.set(x, y, Alpha(15));
+                    }
+                });
+                let glyph = BitmapGlyph {
+                    bitmap: bitmap,
+                    advance: w as i32,
+                };
+                font.glyphs.insert(c, glyph);
+            }
+        }
+        Ok(font)
+    }
+    fn height(&self) -> i32 {
+        self.ascent + self.descent
+    }
+    /// Magnification for `scale`
+    fn factor(&self, scale: f32) -> u8 {
+        cmp::max(1, scale as i32 / cmp::max(1, self.height())) as u8
+    }
+}
+
+/// Parse lines from STARTCHAR to ENDCHAR
+fn parse_bdf_char<'a, I>(lines: &mut I) -> Result<(Option<char>, BitmapGlyph), BitmapFontError>
+where
+    I: Iterator<Item = &'a str>,
+{
+    let (mut c, mut advance, mut bbx) = (None, 0, None);
+    while let Some(line) = lines.next() {
+        let mut words = line.split_whitespace();
+        let key = words.next().unwrap_or("");
+        let args: Vec<i32> = words.filter_map(|w| w.parse().ok()).collect();
+        match key {
+            "ENCODING" if !args.is_empty() => {
+                c = if args[0] < 0 {
+                    None
+                } else {
+                    ::std::char::from_u32(args[0] as u32)
+                };
+            }
+            "DWIDTH" if !args.is_empty() => advance = args[0],
+            "BBX" if args.len() == 4 => bbx = Some((args[0], args[1], args[2], args[3])),
+            "BITMAP" => {
+                let (w, h, x, y) = match bbx {
+                    Some(b) if b.0 >= 0 && b.1 >= 0 => b,
+                    _ => return format_err("BITMAP without BBX".to_owned()),
+                };
+                let mut bitmap = FontCache::new(w as usize, h as usize, (x, -(y + h)));
+                for row in 0..h as u32 {
+                    let hex = lines.next().unwrap_or("");
+                    if !hex.is_ascii() {
+                        return format_err(format!("wrong BITMAP line: {}", hex));
+                    }
+                    let bytes = (0..hex.len() / 2)
+                        .map(|i| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16))
+                        .collect::<Result<Vec<_>, _>>();
+                    let bytes = match bytes {
+                        Ok(ref b) if b.len() * 8 >= w as usize => b.clone(),
+                        _ => return format_err(format!("wrong BITMAP line: {}", hex)),
+                    };
+                    for col in 0..w as u32 {
+                        let byte = bytes[col as usize / 8];
+                        if byte & (0x80 >> (col % 8)) != 0 {
+                            bitmap.set(col, row, Alpha(15));
+                        }
+                    }
+                }
+                let glyph = BitmapGlyph {
+                    bitmap: bitmap,
+                    advance: advance,
+                };
+                return match lines.next() {
+                    Some("ENDCHAR") => Ok((c, glyph)),
+                    l => format_err(format!("expected ENDCHAR, found {:?}", l)),
+                };
+            }
+            _ => {}
+        }
+    }
+    format_err("unexpected EOF in STARTCHAR".to_owned())
+}
+
+impl FontMetrics for BitmapFont {
+    fn advance(&self, c: char, scale: f32) -> Option<f32> {
+        let k = i32::from(self.factor(scale));
+        self.glyphs.get(&c).map(|g| (g.advance * k) as f32)
+    }
+    fn kerning(&self, _a: char, _b: char, _scale: f32) -> f32 {
+        0.0
+    }
+    fn line_metrics(&self, scale: f32) -> LineMetrics {
+        let k = i32::from(self.factor(scale));
+        LineMetrics {
+            ascent: (self.ascent * k) as f32,
+            descent: (-self.descent * k) as f32,
+            line_gap: 0.0,
+        }
+    }
+}
+
+impl TextFont for BitmapFont {
+    fn glyph(&mut self, c: char, scale: u8) -> Option<&FontCache> {
+        let k = self.factor(f32::from(scale));
+        let glyph = self.glyphs.get(&c)?;
+        if k == 1 {
+            return Some(&glyph.bitmap);
+        }
+        let magnified = self.magnified
+            .entry((c, k))
+            .or_insert_with(|| glyph.bitmap.magnify(k));
+        Some(magnified)
+    }
+}
+
+#[cfg(test)]
+mod bitmap_font_test {
+    use super::*;
+    use font::{FontError, FontSetting, TextDraw};
+    use image::Rgba;
+    const BLACK: Rgba<u8> = Rgba { data: [0, 0, 0, 255] };
+    const WHITE: Rgba<u8> = Rgba { data: [255; 4] };
+    fn tiny() -> BitmapFont {
+        BitmapFont::load(Path::new("../test-assets/font/tiny.bdf")).unwrap()
+    }
+    fn ink(img: &RgbaImage) -> Vec<(u32, u32)> {
+        img.enumerate_pixels()
+            .filter(|(_, _, p)| **p == BLACK)
+            .map(|(x, y, _)| (x, y))
+            .collect()
+    }
+    #[test]
+    fn bdf_exact() {
+        let mut font = tiny();
+        let mut setting = FontSetting::new();
+        setting.scale(8);
+        let mut img = RgbaImage::from_pixel(16, 16, WHITE);
+        font.draw(&mut img, 'A', &setting).unwrap();
+        let dots = ink(&img);
+        assert_eq!(dots.len(), 16);
+        assert!(dots.contains(&(2, 0)));
+        assert!(dots.contains(&(0, 4)) && dots.contains(&(4, 4)));
+        assert!(!dots.contains(&(2, 1)));
+        // descender goes below the baseline
+        let mut img = RgbaImage::from_pixel(16, 16, WHITE);
+        font.draw(&mut img, 'g', &setting).unwrap();
+        assert!(ink(&img).iter().all(|&(_, y)| 2 <= y && y < 8));
+        assert!(ink(&img).contains(&(1, 7)));
+    }
+    #[test]
+    fn magnified() {
+        let mut font = tiny();
+        let mut setting = FontSetting::new();
+        setting.scale(16);
+        let mut img = RgbaImage::from_pixel(16, 16, WHITE);
+        font.draw(&mut img, 'A', &setting).unwrap();
+        let dots = ink(&img);
+        assert_eq!(dots.len(), 16 * 4);
+        assert!(dots.contains(&(4, 0)) && dots.contains(&(5, 1)));
+        assert!(!dots.contains(&(3, 0)));
+    }
+    #[test]
+    fn grid() {
+        let mut buf = RgbaImage::from_pixel(16, 8, Rgba { data: [0; 4] });
+        buf.put_pixel(2, 0, BLACK);
+        buf.put_pixel(8, 7, BLACK);
+        let mut font = BitmapFont::from_grid(&buf, "8 8 7\nAB").unwrap();
+        let mut setting = FontSetting::new();
+        setting.scale(8).start((1.0, 1.0));
+        let mut img = RgbaImage::from_pixel(24, 16, WHITE);
+        let bbox = font.draw_str(&mut img, "AB", &setting).unwrap();
+        assert_eq!(ink(&img), vec![(3, 1), (9, 8)]);
+        assert_eq!((bbox.size.width, bbox.size.height), (16, 8));
+        assert!(BitmapFont::from_grid(&buf, "8 8 7\nABC").is_err());
+    }
+    #[test]
+    fn grid_comments() {
+        let mut buf = RgbaImage::from_pixel(16, 16, Rgba { data: [0; 4] });
+        buf.put_pixel(9, 8, BLACK);
+        let mut font = BitmapFont::from_grid(&buf, "# comment\n8 8 7\n#A\nBC").unwrap();
+        assert!(font.advance('#', 8.0).is_some());
+        // 'C' is in the 2nd row, not moved up to the 1st row
+        let mut img = RgbaImage::from_pixel(16, 16, WHITE);
+        font.draw(&mut img, 'C', &FontSetting::new().scale(8)).unwrap();
+        assert_eq!(ink(&img), vec![(1, 0)]);
+    }
+    #[test]
+    fn broken_bdf() {
+        let bdf = "FONT_ASCENT 7\nFONT_DESCENT 1\nSTARTCHAR A\nENCODING 65\nBBX 8 1 0 0\n\
+                   BITMAP\nあ\nENDCHAR\n";
+        match BitmapFont::from_bdf(bdf) {
+            Err(BitmapFontError::Format(_)) => {}
+            r => panic!("{:?}", r),
+        }
+    }
+    #[test]
+    fn switch_fonts() {
+        let mut buf = RgbaImage::from_pixel(16, 8, Rgba { data: [0; 4] });
+        buf.put_pixel(2, 0, BLACK);
+        let grid = BitmapFont::from_grid(&buf, "8 8 7\nAB").unwrap();
+        let mut fonts: Vec<Box<dyn TextFont>> = vec![Box::new(tiny()), Box::new(grid)];
+        let setting = FontSetting::new();
+        for font in &mut fonts {
+            let mut img = RgbaImage::from_pixel(32, 32, WHITE);
+            font.draw_str(&mut img, "AB", &setting).unwrap();
+            match font.draw_str(&mut img, "AZ", &setting) {
+                Err(FontError::NoFont('Z')) => {}
+                r => panic!("{:?}", r),
+            }
+        }
+    }
+}
@@ -0,0 +1,274 @@
+//! camera and viewport
+
+use euclid::{point2, size2, vec2};
+use tuple_map::TupleMap2;
+
+use std::cmp::{max, min};
+
+use frame::dottypes::*;
+use Clock;
+
+/// Camera converts world coordinates(the whole level) into screen coordinates.
+/// All coordinates are in DotSpace, and the screen is the 320×240 dot buffer.
+#[derive(Clone, Debug)]
+pub struct Camera {
+    /// upper left corner of the viewport in world coordinates
+    position: DotPoint,
+    /// position with sub-dot precision, used for smoothing
+    exact: (f32, f32),
+    /// size of the viewport
+    size: DotSize,
+    /// the camera never shows outside of bounds
+    bounds: Option<DotRect>,
+    /// while the target is in deadzone(screen coordinates), the camera doesn't move
+    deadzone: DotRect,
+    /// how fast the camera catches up with the target (0 < smoothing <= 1)
+    smoothing: f32,
+    shake: Shake,
+}
+
+impl Default for Camera {
+    fn default() -> Camera {
+        Camera::new()
+    }
+}
+
+impl Camera {
+    /// the smallest smoothing, with which the camera still moves
+    pub const MIN_SMOOTHING: f32 = 0.01;
+    pub fn new() -> Camera {
+        let size = size2(DOT_WIDTH as i16, DOT_HEIGHT as i16);
+        Camera {
+            position: point2(0, 0),
+            exact: (0.0, 0.0),
+            size: size,
+            bounds: None,
+            deadzone: DotRect::new(point2(size.width / 2, size.height / 2), size2(0, 0)),
+            smoothing: 1.0,
+            shake: Shake::default(),
+        }
+    }
+    pub fn size(&mut self, size: DotSize) -> &mut Camera {
+        self.size = size;
+        self.clamp();
+        self
+    }
+    pub fn bounds(&mut self, bounds: DotRect) -> &mut Camera {
+        self.bounds = Some(bounds);
+        self.clamp();
+        self
+    }
+    pub fn deadzone(&mut self, deadzone: DotRect) -> &mut Camera {
+        self.deadzone = deadzone;
+        self
+    }
+    /// How fast the camera catches up with the target, clamped into
+    /// MIN_SMOOTHING..=1.0(1.0 means immediately)
+    pub fn smoothing(&mut self, s: f32) -> &mut Camera {
+        self.smoothing = s.max(Self::MIN_SMOOTHING).min(1.0);
+        self
+    }
+    /// Move the upper left corner of the viewport to `p` immediately.
+    pub fn move_to(&mut self, p: DotPoint) {
+        self.exact = (f32::from(p.x), f32::from(p.y));
+        self.clamp();
+    }
+    /// Move the camera so that `target`(world coordinates) gets into the deadzone.
+    /// Call this once per tick.
+    pub fn follow(&mut self, target: DotPoint) {
+        let on_screen = target - self.position.to_vector();
+        let dz = self.deadzone;
+        let diff = |p: i16, min_edge: i16, max_edge: i16| {
+            if p < min_edge {
+                p - min_edge
+            } else if p > max_edge {
+                p - max_edge
+            } else {
+                0
+            }
+        };
+        let dx = diff(on_screen.x, dz.min_x(), dz.max_x());
+        let dy = diff(on_screen.y, dz.min_y(), dz.max_y());
+        let desired = (self.position.x + dx, self.position.y + dy).map(f32::from);
+        let s = self.smoothing;
+        self.exact.0 += (desired.0 - self.exact.0) * s;
+        self.exact.1 += (desired.1 - self.exact.1) * s;
+        self.clamp();
+    }
+    /// Start screen shake, which decays linearly in `duration` ticks.
+    pub fn shake(&mut self, amplitude: i16, duration: Clock) {
+        self.shake.amplitude = amplitude;
+        self.shake.duration = duration;
+        self.shake.remaining = duration;
+    }
+    /// Advance screen shake. Call this once per tick.
+    pub fn update(&mut self) {
+        self.shake.update();
+    }
+    /// Upper left corner of the viewport in world coordinates, without shake.
+    pub fn position(&self) -> DotPoint {
+        self.position
+    }
+    /// Upper left corner of the viewport in world coordinates, with shake.
+    pub fn view_origin(&self) -> DotPoint {
+        self.position + self.shake.offset()
+    }
+    /// Visible area in world coordinates
+    pub fn visible_rect(&self) -> DotRect {
+        DotRect::new(self.view_origin(), self.size)
+    }
+    pub fn world_to_screen(&self, p: DotPoint) -> DotPoint {
+        p - self.view_origin().to_vector()
+    }
+    pub fn screen_to_world(&self, p: DotPoint) -> DotPoint {
+        p + self.view_origin().to_vector()
+    }
+    /// Convert window coordinates(e.g. mouse cursor) into world coordinates.
+    /// `scale` is REAL_SCREEN_SIZE / DOT_SCREEN_SIZE.
+    pub fn real_to_world(&self, xy: (f64, f64), scale: (f64, f64)) -> DotPoint {
+        let (x, y) = ((xy.0, scale.0), (xy.1, scale.1)).map(|(p, s)| (p / s).floor() as i16);
+        self.screen_to_world(point2(x, y))
+    }
+    fn clamp(&mut self) {
+        if let Some(bounds) = self.bounds {
+            let clamp_axis = |p: f32, min_edge: i16, len: i16, view: i16| {
+                if len <= view {
+                    // bounds is smaller than the viewport, so centering it
+                    f32::from(min_edge - (view - len) / 2)
+                } else {
+                    let max_edge = f32::from(min_edge + len - view);
+                    p.max(f32::from(min_edge)).min(max_edge)
+                }
+            };
+            self.exact.0 = clamp_axis(
+                self.exact.0,
+                bounds.min_x(),
+                bounds.size.width,
+                self.size.width,
+            );
+            self.exact.1 = clamp_axis(
+                self.exact.1,
+                bounds.min_y(),
+                bounds.size.height,
+                self.size.height,
+            );
+        }
+        let (x, y) = self.exact.map(|f| f.round() as i16);
+        self.position = point2(x, y);
+    }
+}
+
+/// State of screen shake
+#[derive(Clone, Debug)]
+struct Shake {
+    amplitude: i16,
+    duration: Clock,
+    remaining: Clock,
+    /// xorshift state
+    seed: u32,
+    current: DotVector,
+}
+
+impl Default for Shake {
+    fn default() -> Shake {
+        Shake {
+            amplitude: 0,
+            duration: 0,
+            remaining: 0,
+            seed: 0x2545_f491,
+            current: vec2(0, 0),
+        }
+    }
+}
+
+impl Shake {
+    fn next_rand(&mut self) -> u32 {
+        let mut x = self.seed;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.seed = x;
+        x
+    }
+    fn update(&mut self) {
+        if self.remaining == 0 {
+            self.current = vec2(0, 0);
+            return;
+        }
+        let amp = i64::from(self.amplitude) * self.remaining as i64 / max(self.duration, 1) as i64;
+        let amp = max(min(amp, i64::from(i16::max_value())), 0) as i16;
+        let width = 2 * i64::from(amp) + 1;
+        let mut rand_axis = || (i64::from(self.next_rand()) % width) as i16 - amp;
+        let (x, y) = (rand_axis(), rand_axis());
+        self.current = vec2(x, y);
+        self.remaining -= 1;
+    }
+    fn offset(&self) -> DotVector {
+        self.current
+    }
+}
+
+#[cfg(test)]
+mod camera_test {
+    use super::*;
+    use euclid::rect;
+    #[test]
+    fn clamp_to_bounds() {
+        let mut camera = Camera::new();
+        camera.bounds(rect(0, 0, 640, 480));
+        camera.move_to(point2(-30, 400));
+        assert_eq!(camera.position(), point2(0, 240));
+        camera.move_to(point2(100, 100));
+        assert_eq!(camera.position(), point2(100, 100));
+    }
+    #[test]
+    fn small_bounds_is_centered() {
+        let mut camera = Camera::new();
+        camera.bounds(rect(0, 0, 160, 240));
+        camera.move_to(point2(50, 50));
+        assert_eq!(camera.position(), point2(-80, 0));
+    }
+    #[test]
+    fn follow_deadzone() {
+        let mut camera = Camera::new();
+        camera.deadzone(rect(100, 80, 120, 80));
+        camera.follow(point2(150, 100));
+        assert_eq!(camera.position(), point2(0, 0));
+        camera.follow(point2(250, 100));
+        assert_eq!(camera.position(), point2(30, 0));
+        assert_eq!(camera.world_to_screen(point2(250, 100)), point2(220, 100));
+    }
+    #[test]
+    fn follow_smoothing() {
+        let mut camera = Camera::new();
+        camera.smoothing(0.5);
+        camera.follow(point2(260, 120));
+        assert_eq!(camera.position(), point2(50, 0));
+        camera.follow(point2(260, 120));
+        assert_eq!(camera.position(), point2(75, 0));
+        // 0 would stop the camera
+        camera.smoothing(0.0);
+        (0..100).for_each(|_| camera.follow(point2(360, 120)));
+        assert!(camera.position().x > 75);
+    }
+    #[test]
+    fn screen_world_roundtrip() {
+        let mut camera = Camera::new();
+        camera.move_to(point2(123, 45));
+        let p = point2(10, 20);
+        assert_eq!(camera.world_to_screen(camera.screen_to_world(p)), p);
+        assert_eq!(camera.real_to_world((20.0, 40.0), (2.0, 2.0)), point2(133, 65));
+    }
+    #[test]
+    fn shake_ends() {
+        let mut camera = Camera::new();
+        camera.shake(4, 10);
+        (0..10).for_each(|_| {
+            camera.update();
+            let v = camera.view_origin() - camera.position();
+            assert!(v.x.abs() <= 4 && v.y.abs() <= 4);
+        });
+        camera.update();
+        assert_eq!(camera.view_origin(), camera.position());
+    }
+}
@@ -0,0 +1,418 @@
+//! collision layers and broad-phase collision detection
+
+use euclid::{point2, size2};
+
+use std::cmp::{max, min, Ordering};
+use std::collections::HashMap;
+use std::ops::BitOr;
+
+use frame::dottypes::*;
+use frame::{Collide, Contact, RayHit};
+
+/// Number of collision layers, which equals to the number of collision bits in alpha
+pub const LAYER_NUM: usize = 4;
+
+/// Named collision layer, which is mapped to one of the collision bits in alpha
+/// (see tile::AltenaAlpha)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CollisionLayer(u8);
+
+impl CollisionLayer {
+    pub const PLAYER_BODY: CollisionLayer = CollisionLayer(0);
+    pub const PLAYER_HURTBOX: CollisionLayer = CollisionLayer(1);
+    pub const ENEMY_BULLET: CollisionLayer = CollisionLayer(2);
+    pub const WALL: CollisionLayer = CollisionLayer(3);
+    /// Layer of the `index`th collision bit
+    pub fn new(index: u8) -> Option<CollisionLayer> {
+        if (index as usize) < LAYER_NUM {
+            Some(CollisionLayer(index))
+        } else {
+            None
+        }
+    }
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+    /// Collision bit of the layer
+    pub fn bit(self) -> u8 {
+        1 << self.0
+    }
+    pub fn variants() -> impl Iterator<Item = CollisionLayer> {
+        (0..LAYER_NUM as u8).map(CollisionLayer)
+    }
+}
+
+/// Which layer collides with which layer.
+/// Default matrix lets each layer collide only with itself, so dots collide when they
+/// share collision bits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LayerMatrix {
+    /// rows[i] is the bit set of layers which layer i collides with
+    rows: [u8; LAYER_NUM],
+}
+
+impl Default for LayerMatrix {
+    fn default() -> LayerMatrix {
+        let mut rows = [0; LAYER_NUM];
+        CollisionLayer::variants().for_each(|l| rows[l.index()] = l.bit());
+        LayerMatrix { rows: rows }
+    }
+}
+
+impl LayerMatrix {
+    /// Matrix where no layer collides
+    pub fn empty() -> LayerMatrix {
+        LayerMatrix {
+            rows: [0; LAYER_NUM],
+        }
+    }
+    /// Let `a` and `b` collide
+    pub fn enable(&mut self, a: CollisionLayer, b: CollisionLayer) -> &mut LayerMatrix {
+        self.rows[a.index()] |= b.bit();
+        self.rows[b.index()] |= a.bit();
+        self
+    }
+    /// Don't let `a` and `b` collide
+    pub fn disable(&mut self, a: CollisionLayer, b: CollisionLayer) -> &mut LayerMatrix {
+        self.rows[a.index()] &= !b.bit();
+        self.rows[b.index()] &= !a.bit();
+        self
+    }
+    pub fn collides(&self, a: CollisionLayer, b: CollisionLayer) -> bool {
+        self.rows[a.index()] & b.bit() != 0
+    }
+    pub(crate) fn transpose(&self) -> LayerMatrix {
+        let mut rows = [0; LAYER_NUM];
+        for i in 0..LAYER_NUM {
+            for j in 0..LAYER_NUM {
+                if self.rows[i] & (1 << j) != 0 {
+                    rows[j] |= 1 << i;
+                }
+            }
+        }
+        LayerMatrix { rows: rows }
+    }
+    /// Layer pairs which hit between two mesh rows(16 dots × 4 bits)
+    pub(crate) fn hits(&self, row_s: u64, row_o: u64) -> LayerPairs {
+        // the lowest bit of each nibble
+        const LSB: u64 = 0x1111_1111_1111_1111;
+        if row_s == 0 || row_o == 0 {
+            return LayerPairs::default();
+        }
+        let mut res = 0u16;
+        for i in 0..LAYER_NUM {
+            let occupied_s = (row_s >> i) & LSB;
+            if occupied_s == 0 {
+                continue;
+            }
+            for j in (0..LAYER_NUM).filter(|j| self.rows[i] & (1 << j) != 0) {
+                let occupied_o = (row_o >> j) & LSB;
+                if occupied_s & occupied_o != 0 {
+                    res |= 1 << (i * LAYER_NUM + j);
+                }
+            }
+        }
+        LayerPairs(res)
+    }
+}
+
+/// Set of (layer of self, layer of the other) pairs
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LayerPairs(u16);
+
+impl LayerPairs {
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+    pub fn contains(&self, s: CollisionLayer, o: CollisionLayer) -> bool {
+        self.0 & (1 << (s.index() * LAYER_NUM + o.index())) != 0
+    }
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = (CollisionLayer, CollisionLayer)> + 'a {
+        CollisionLayer::variants()
+            .flat_map(|s| CollisionLayer::variants().map(move |o| (s, o)))
+            .filter(move |&(s, o)| self.contains(s, o))
+    }
+    /// Collision bits of self side
+    pub fn bits_s(&self) -> u8 {
+        self.iter().fold(0, |acc, (s, _)| acc | s.bit())
+    }
+    /// Collision bits of the other side
+    pub fn bits_o(&self) -> u8 {
+        self.iter().fold(0, |acc, (_, o)| acc | o.bit())
+    }
+    /// Pairs seen from the other side
+    pub fn transpose(&self) -> LayerPairs {
+        LayerPairs(
+            self.iter()
+                .fold(0, |acc, (s, o)| acc | 1 << (o.index() * LAYER_NUM + s.index())),
+        )
+    }
+}
+
+impl BitOr for LayerPairs {
+    type Output = LayerPairs;
+    fn bitor(self, rhs: LayerPairs) -> LayerPairs {
+        LayerPairs(self.0 | rhs.0)
+    }
+}
+
+/// Uniform grid over world bounding boxes.
+/// Objects are only tested against objects in the same cells, so we don't need O(n²)
+/// narrow-phase calls.
+#[derive(Clone, Debug)]
+pub struct CollisionGrid {
+    cell_size: i16,
+    cells: HashMap<(i16, i16), Vec<usize>>,
+    bboxes: Vec<DotRect>,
+}
+
+impl CollisionGrid {
+    pub const DEFAULT_CELL_SIZE: i16 = 32;
+    pub fn new(cell_size: i16) -> CollisionGrid {
+        assert!(cell_size > 0, "cell size must be positive");
+        CollisionGrid {
+            cell_size: cell_size,
+            cells: HashMap::new(),
+            bboxes: vec![],
+        }
+    }
+    /// Remove all objects. Cells are removed too, so that cells visited by moving
+    /// objects don't pile up.
+    pub fn clear(&mut self) {
+        self.cells.clear();
+        self.bboxes.clear();
+    }
+    /// Register an object's bounding box(world coordinates) and return its id.
+    /// Ids are given in the order of insertion.
+    pub fn insert(&mut self, bbox: DotRect) -> usize {
+        let id = self.bboxes.len();
+        self.bboxes.push(bbox);
+        for cell in self.cells_of(bbox) {
+            self.cells.entry(cell).or_insert_with(Vec::new).push(id);
+        }
+        id
+    }
+    pub fn len(&self) -> usize {
+        self.bboxes.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.bboxes.is_empty()
+    }
+    /// All pairs (i, j)(i < j) whose bounding boxes intersect.
+    /// Each pair appears only once.
+    pub fn candidates(&self) -> Vec<(usize, usize)> {
+        let mut res = vec![];
+        for (&cell, ids) in &self.cells {
+            for (n, &i) in ids.iter().enumerate() {
+                for &j in &ids[n + 1..] {
+                    let inter = match self.bboxes[i].intersection(&self.bboxes[j]) {
+                        Some(r) => r,
+                        None => continue,
+                    };
+                    // report the pair only in the cell which has the corner of intersection
+                    if self.cell_of(inter.origin) != cell {
+                        continue;
+                    }
+                    res.push((min(i, j), max(i, j)));
+                }
+            }
+        }
+        res
+    }
+    /// Ids of objects whose bounding boxes intersect `rect`
+    pub fn query(&self, rect: DotRect) -> Vec<usize> {
+        let mut res: Vec<usize> = self.cells_of(rect)
+            .filter_map(|cell| self.cells.get(&cell))
+            .flat_map(|ids| ids.iter().cloned())
+            .filter(|&id| self.bboxes[id].intersects(&rect))
+            .collect();
+        res.sort();
+        res.dedup();
+        res
+    }
+    fn cell_of(&self, p: DotPoint) -> (i16, i16) {
+        (floor_div(p.x, self.cell_size), floor_div(p.y, self.cell_size))
+    }
+    fn cells_of(&self, rect: DotRect) -> impl Iterator<Item = (i16, i16)> {
+        let start = self.cell_of(rect.origin);
+        let end = self.cell_of(DotPoint::new(
+            rect.max_x() - 1,
+            rect.max_y() - 1,
+        ));
+        (start.1..end.1 + 1).flat_map(move |y| (start.0..end.0 + 1).map(move |x| (x, y)))
+    }
+}
+
+impl Default for CollisionGrid {
+    fn default() -> CollisionGrid {
+        CollisionGrid::new(Self::DEFAULT_CELL_SIZE)
+    }
+}
+
+fn floor_div(a: i16, b: i16) -> i16 {
+    if a >= 0 {
+        a / b
+    } else {
+        (a + 1) / b - 1
+    }
+}
+
+fn fill_grid<C: Collide>(grid: &mut CollisionGrid, objects: &[C]) {
+    grid.clear();
+    objects.iter().for_each(|o| {
+        grid.insert(o.world_bbox());
+    });
+}
+
+/// Find all colliding pairs in `objects`, between layers allowed by `matrix`.
+/// Returned pairs (i, j, contact) satisfy i < j, and contact is seen from objects[i].
+pub fn collide_all<C: Collide>(
+    grid: &mut CollisionGrid,
+    objects: &[C],
+    matrix: &LayerMatrix,
+) -> Vec<(usize, usize, Contact)> {
+    fill_grid(grid, objects);
+    let mut res: Vec<_> = grid.candidates()
+        .into_iter()
+        .filter_map(|(i, j)| {
+            let contact = objects[i].collide_with(&objects[j], matrix)?;
+            Some((i, j, contact))
+        })
+        .collect();
+    res.sort_by_key(|&(i, j, _)| (i, j));
+    res
+}
+
+/// Cast a ray from `start` to `end` against `objects` and return the nearest hit.
+/// `grid` is refilled with `objects` like `collide_all`.
+pub fn raycast_all<C: Collide>(
+    grid: &mut CollisionGrid,
+    objects: &[C],
+    start: DotPoint,
+    end: DotPoint,
+    mask: u8,
+) -> Option<(usize, RayHit)> {
+    let (x0, x1) = (min(start.x, end.x), max(start.x, end.x));
+    let (y0, y1) = (min(start.y, end.y), max(start.y, end.y));
+    let area = DotRect::new(point2(x0, y0), size2(x1 - x0 + 1, y1 - y0 + 1));
+    fill_grid(grid, objects);
+    grid.query(area)
+        .into_iter()
+        .filter_map(|i| Some((i, objects[i].raycast(start, end, mask)?)))
+        .min_by(|(_, a), (_, b)| {
+            a.distance
+                .partial_cmp(&b.distance)
+                .unwrap_or(Ordering::Equal)
+        })
+}
+
+#[cfg(test)]
+mod collision_test {
+    use super::*;
+    use euclid::rect;
+    use frame::MeshTree;
+    use testutils::load_mesh;
+    struct Obj<'a> {
+        origin: DotPoint,
+        mesh: &'a MeshTree,
+    }
+    impl<'a> Collide for Obj<'a> {
+        fn origin(&self) -> DotPoint {
+            self.origin
+        }
+        fn mesh(&self) -> &MeshTree {
+            self.mesh
+        }
+    }
+    #[test]
+    fn matrix_hits() {
+        let (body, hurt, bullet) = (
+            CollisionLayer::PLAYER_BODY,
+            CollisionLayer::PLAYER_HURTBOX,
+            CollisionLayer::ENEMY_BULLET,
+        );
+        let mut matrix = LayerMatrix::empty();
+        matrix.enable(hurt, bullet);
+        assert!(matrix.collides(bullet, hurt));
+        assert!(!matrix.collides(body, bullet));
+        // dot 0 has body and hurtbox, dot 1 has bullet
+        let row_s = u64::from(body.bit() | hurt.bit()) << 60;
+        let row_o = u64::from(bullet.bit()) << 60;
+        let pairs = matrix.hits(row_s, row_o);
+        assert_eq!(pairs.iter().collect::<Vec<_>>(), vec![(hurt, bullet)]);
+        assert_eq!(pairs.bits_s(), hurt.bit());
+        assert_eq!(pairs.bits_o(), bullet.bit());
+        assert!(pairs.transpose().contains(bullet, hurt));
+        assert!(matrix.hits(row_s, row_o >> 4).is_empty());
+        matrix.disable(hurt, bullet);
+        assert!(matrix.hits(row_s, row_o).is_empty());
+    }
+    #[test]
+    fn grid_candidates() {
+        let mut grid = CollisionGrid::new(16);
+        let a = grid.insert(rect(0, 0, 40, 40));
+        let b = grid.insert(rect(30, 30, 10, 10));
+        let c = grid.insert(rect(-20, -20, 10, 10));
+        let d = grid.insert(rect(-15, -15, 20, 20));
+        let mut cands = grid.candidates();
+        cands.sort();
+        assert_eq!(cands, vec![(a, b), (a, d), (c, d)]);
+        assert_eq!(grid.query(rect(35, 35, 1, 1)), vec![a, b]);
+    }
+    #[test]
+    fn grid_clear() {
+        let mut grid = CollisionGrid::new(16);
+        for i in 0..100 {
+            grid.clear();
+            grid.insert(rect(i * 16, 0, 10, 10));
+        }
+        assert_eq!(grid.cells.len(), 1);
+        assert_eq!(grid.query(rect(99 * 16, 0, 1, 1)), vec![0]);
+    }
+    #[test]
+    fn same_as_brute_force() {
+        let bullet = load_mesh("../test-assets/bullet.png");
+        let chara1 = load_mesh("../test-assets/chara1.png");
+        let chara2 = load_mesh("../test-assets/chara2.png");
+        let meshes = [&bullet, &chara1, &chara2];
+        let objects: Vec<_> = (0..300)
+            .map(|i: i16| Obj {
+                origin: point2((i * 37) % 320 - 20, (i * 53) % 240 - 20),
+                mesh: meshes[i as usize % 3],
+            })
+            .collect();
+        let mut grid = CollisionGrid::default();
+        let res = collide_all(&mut grid, &objects, &LayerMatrix::default());
+        let mut expected = vec![];
+        for i in 0..objects.len() {
+            for j in i + 1..objects.len() {
+                if let Some(c) = objects[i].collide(&objects[j]) {
+                    expected.push((i, j, c));
+                }
+            }
+        }
+        assert!(!expected.is_empty());
+        assert_eq!(res, expected);
+    }
+    #[test]
+    fn raycast_nearest() {
+        let chara1 = load_mesh("../test-assets/chara1.png");
+        let objects: Vec<_> = [80, 20, 50, 200]
+            .iter()
+            .map(|&x| Obj {
+                origin: point2(x, 0),
+                mesh: &chara1,
+            })
+            .collect();
+        let mut grid = CollisionGrid::default();
+        let (start, end) = (point2(0, 8), point2(150, 8));
+        let (i, hit) = raycast_all(&mut grid, &objects, start, end, 0xf).unwrap();
+        assert_eq!(i, 1);
+        assert_eq!(hit, objects[1].raycast(start, end, 0xf).unwrap());
+        assert_eq!(hit.point.y, 8);
+        assert!(hit.point.x >= 22 && hit.point.x < 33);
+        let (i, _) = raycast_all(&mut grid, &objects, end, start, 0xf).unwrap();
+        assert_eq!(i, 0);
+        assert_eq!(raycast_all(&mut grid, &objects, point2(0, 30), point2(300, 30), 0xf), None);
+    }
+}
@@ -1,11 +1,16 @@
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 
-use rusttype::{point, Font, FontCollection, Point, Scale};
+use euclid::{rect, vec2};
+use rusttype::{point, Font, FontCollection, Glyph, Point, Scale, ScaledGlyph};
+use std::cmp;
 use std::error::Error;
 use std::fmt;
 
-use rect_iter::{GetMut2D, IndexError, IntoTuple2, RectRange};
+use frame::dottypes::*;
+use markup::{self, RichText};
+use rect_iter::{GetMut2D, IntoTuple2};
+use text::{self, Align, FontMetrics, LineMetrics, PlacedGlyph};
 use tile::tiletypes::*;
 use tile::{Alpha, Blend, Color};
 use tuple_map::TupleMap2;
@@ -13,7 +18,20 @@ use tuple_map::TupleMap2;
 #[derive(Copy, Clone, Debug)]
 pub enum FontError {
     NoFont(char),
-    Index(IndexError),
+    /// some dots are out of the buffer(only in strict mode)
+    OutOfRange(ClipError),
+}
+
+/// Dots of text out of the buffer, found before drawing in strict mode
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ClipError {
+    /// the first character out of the buffer and its index in the text
+    pub c: char,
+    pub index: usize,
+    /// bounding rect of all dots out of the buffer
+    pub region: DotRect,
+    /// number of dots out of the buffer
+    pub dots: usize,
 }
 
 impl Error for FontError {
@@ -26,7 +44,341 @@ impl fmt::Display for FontError {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match self {
             FontError::NoFont(c) => write!(f, "NoFont: {}", c),
-            FontError::Index(id) => write!(f, "Index: {}", id),
+            FontError::OutOfRange(e) => write!(
+                f,
+                "OutOfRange: {} dots in {:?} from '{}'(index {})",
+                e.dots, e.region, e.c, e.index
+            ),
+        }
+    }
+}
+
+/// Font which can be drawn by the text renderer.
+/// TrueType fonts(FontHandle) and bitmap fonts(BitmapFont) implement this, so
+/// call sites don't depend on the kind of font.
+pub trait TextFont: FontMetrics {
+    /// Coverage bitmap of `c` at `scale`, or None if the font doesn't have it
+    fn glyph(&mut self, c: char, scale: u8) -> Option<&FontCache>;
+}
+
+/// Drawing functions for all fonts
+pub trait TextDraw {
+    /// Draw `c` with `setting.start` as the upper left corner of the line
+    fn draw<B, I>(&mut self, buf: &mut B, c: char, setting: &FontSetting) -> Result<(), FontError>
+    where
+        B: GetMut2D<Item = I>,
+        I: Blend;
+    /// Draw `s` with `setting.start` as the upper left corner, wrapping lines at
+    /// `setting.max_width`. Returns the bounding rect of the text, including effects.
+    fn draw_str<B, I>(
+        &mut self,
+        buf: &mut B,
+        s: &str,
+        setting: &FontSetting,
+    ) -> Result<DotRect, FontError>
+    where
+        B: GetMut2D<Item = I>,
+        I: Blend;
+    /// Draw rich text with per-span colors and ruby.
+    /// Ruby is drawn above the first line(right of the first column in vertical
+    /// text), so leave headroom of half the line height above `setting.start`, or
+    /// it's clipped. Returns the bounding rect of the text, including ruby and effects.
+    fn draw_rich<B, I>(
+        &mut self,
+        buf: &mut B,
+        text: &RichText,
+        setting: &FontSetting,
+    ) -> Result<DotRect, FontError>
+    where
+        B: GetMut2D<Item = I>,
+        I: Blend;
+}
+
+impl<F: TextFont + ?Sized> TextDraw for F {
+    fn draw<B, I>(&mut self, buf: &mut B, c: char, setting: &FontSetting) -> Result<(), FontError>
+    where
+        B: GetMut2D<Item = I>,
+        I: Blend,
+    {
+        let ascent = self.line_metrics(f32::from(setting.scale_u)).ascent;
+        let g = PlacedGlyph {
+            c: c,
+            index: 0,
+            x: setting.start.x,
+            y: setting.start.y + ascent,
+            line: 0,
+            rotated: false,
+            cell: DotRect::zero(),
+        };
+        draw_glyphs(self, buf, &[(g, setting.scale_u, setting.color)], setting)
+    }
+    fn draw_str<B, I>(
+        &mut self,
+        buf: &mut B,
+        s: &str,
+        setting: &FontSetting,
+    ) -> Result<DotRect, FontError>
+    where
+        B: GetMut2D<Item = I>,
+        I: Blend,
+    {
+        let layout = text::layout(self, s, setting)?;
+        let (scale, color) = (setting.scale_u, setting.color);
+        let glyphs: Vec<_> = layout.glyphs.iter().map(|&g| (g, scale, color)).collect();
+        draw_glyphs(self, buf, &glyphs, setting)?;
+        Ok(setting.effects.ink_rect(layout.bbox))
+    }
+    fn draw_rich<B, I>(
+        &mut self,
+        buf: &mut B,
+        text: &RichText,
+        setting: &FontSetting,
+    ) -> Result<DotRect, FontError>
+    where
+        B: GetMut2D<Item = I>,
+        I: Blend,
+    {
+        markup::draw_rich(self, buf, text, setting, text.len())
+    }
+}
+
+/// Layers of glyphs with effects. Each pass is drawn over the whole text in
+/// this order, so that effects of a glyph don't cover bodies of its neighbors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Pass {
+    Shadow,
+    Outline,
+    Body,
+}
+
+const PASSES: [Pass; 3] = [Pass::Shadow, Pass::Outline, Pass::Body];
+
+/// Draw glyphs placed by layout, each with its (scale, color).
+/// Dots out of `buf` are clipped, or in strict mode, nothing is drawn if any
+/// dot is out of `buf`.
+pub(crate) fn draw_glyphs<F, B, I>(
+    font: &mut F,
+    buf: &mut B,
+    glyphs: &[(PlacedGlyph, u8, Color)],
+    setting: &FontSetting,
+) -> Result<(), FontError>
+where
+    F: TextFont + ?Sized,
+    B: GetMut2D<Item = I>,
+    I: Blend,
+{
+    let effects = &setting.effects;
+    // effects are applied once per glyph, not once per pass
+    let mut res = Ok(());
+    let mut shapes = Vec::with_capacity(glyphs.len());
+    for &(g, scale, color) in glyphs {
+        match GlyphShapes::new(font, &g, scale, color, effects) {
+            Ok(s) => shapes.push((g, s)),
+            Err(e) => res = Err(e),
+        }
+    }
+    if setting.strict {
+        res?;
+        check_glyphs(buf, &shapes, effects)?;
+    }
+    let mut blend = |p: (i32, i32), color: Color, alpha: Alpha| {
+        if let Ok(b) = buf.get_mut_point_r(p) {
+            b.blend(color, alpha);
+        }
+    };
+    for &pass in PASSES.iter().filter(|&&p| effects.draws(p)) {
+        for (_, shape) in &shapes {
+            shape.dots(effects, pass, &mut blend);
+        }
+    }
+    res
+}
+
+/// Find dots of glyphs out of `buf`, without writing anything
+fn check_glyphs<B, I>(
+    buf: &mut B,
+    shapes: &[(PlacedGlyph, GlyphShapes)],
+    effects: &TextEffects,
+) -> Result<(), FontError>
+where
+    B: GetMut2D<Item = I>,
+    I: Blend,
+{
+    let mut clip: Option<ClipError> = None;
+    for &pass in PASSES.iter().filter(|&&p| effects.draws(p)) {
+        for (g, shape) in shapes {
+            shape.dots(effects, pass, &mut |p, _, _| {
+                if buf.get_mut_point_r(p).is_ok() {
+                    return;
+                }
+                let dot = rect(p.0 as i16, p.1 as i16, 1, 1);
+                match clip {
+                    Some(ref mut e) => {
+                        e.region = e.region.union(&dot);
+                        e.dots += 1;
+                    }
+                    None => {
+                        clip = Some(ClipError {
+                            c: g.c,
+                            index: g.index,
+                            region: dot,
+                            dots: 1,
+                        })
+                    }
+                }
+            });
+        }
+    }
+    match clip {
+        Some(e) => Err(FontError::OutOfRange(e)),
+        None => Ok(()),
+    }
+}
+
+/// Coverage of a glyph placed by layout, and of its outline
+struct GlyphShapes {
+    color: Color,
+    body: Coverage,
+    /// the body grown by the outline thickness
+    outline: Option<Coverage>,
+}
+
+impl GlyphShapes {
+    /// The pen position is rounded to dots, so that cached glyphs can be reused anywhere.
+    fn new<F>(
+        font: &mut F,
+        g: &PlacedGlyph,
+        scale: u8,
+        color: Color,
+        effects: &TextEffects,
+    ) -> Result<GlyphShapes, FontError>
+    where
+        F: TextFont + ?Sized,
+    {
+        // (ascent, size of em square)
+        let rotation = if g.rotated {
+            let m = font.line_metrics(f32::from(scale));
+            Some((m.ascent.round() as i32, (m.ascent - m.descent).round() as i32))
+        } else {
+            None
+        };
+        let cache = match font.glyph(g.c, scale) {
+            Some(cache) => cache,
+            None => return Err(FontError::NoFont(g.c)),
+        };
+        let pen = (g.x, g.y).map(|f| f.round() as i32);
+        let mut body = Coverage::from_cache(cache, pen);
+        if let Some((ascent, em)) = rotation {
+            body = body.rotated((pen.0, pen.1 - ascent), em);
+        }
+        if effects.bold {
+            body = body.emboldened();
+        }
+        let outline = effects.outline.map(|(_, t)| body.dilated(i32::from(t)));
+        Ok(GlyphShapes {
+            color: color,
+            body: body,
+            outline: outline,
+        })
+    }
+    /// Call `f` with (position, color, alpha) for each visible dot of `pass`
+    fn dots(&self, effects: &TextEffects, pass: Pass, f: &mut dyn FnMut((i32, i32), Color, Alpha)) {
+        match (pass, effects.shadow, effects.outline, &self.outline) {
+            (Pass::Body, _, _, _) => self.body.dots(self.color, (0, 0), f),
+            (Pass::Shadow, Some(((dx, dy), shadow_color)), _, outline) => {
+                let shape = outline.as_ref().unwrap_or(&self.body);
+                shape.dots(shadow_color, (i32::from(dx), i32::from(dy)), f);
+            }
+            (Pass::Outline, _, Some((outline_color, _)), Some(shape)) => {
+                shape.dots(outline_color, (0, 0), f);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Alpha values of a glyph placed in the buffer, for applying effects
+struct Coverage {
+    /// upper left corner in the buffer
+    origin: (i32, i32),
+    width: i32,
+    height: i32,
+    inner: Vec<u8>,
+}
+
+impl Coverage {
+    fn new<F>(origin: (i32, i32), width: i32, height: i32, f: F) -> Coverage
+    where
+        F: Fn(i32, i32) -> u8,
+    {
+        let inner = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| f(x, y))
+            .collect();
+        Coverage {
+            origin: origin,
+            width: width,
+            height: height,
+            inner: inner,
+        }
+    }
+    fn from_cache(cache: &FontCache, pen: (i32, i32)) -> Coverage {
+        let (w, h) = (cache.width, cache.height).map(|u| u as i32);
+        Coverage::new(pen.add(cache.bearing), w, h, |x, y| {
+            cache.get(x as u32, y as u32).0
+        })
+    }
+    /// Alpha value at local (x, y), 0 out of the bitmap
+    fn get(&self, x: i32, y: i32) -> u8 {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            return 0;
+        }
+        self.inner[(y * self.width + x) as usize]
+    }
+    /// Rotate by 90 degrees clockwise in the square of `len` at `origin`
+    fn rotated(&self, origin: (i32, i32), len: i32) -> Coverage {
+        let (bx, by) = origin;
+        let (ox, oy) = self.origin;
+        let h = self.height;
+        let new_origin = (bx + len - (oy - by) - h, by + ox - bx);
+        Coverage::new(new_origin, h, self.width, |x, y| self.get(y, h - 1 - x))
+    }
+    /// Faux bold: overlap the glyph with itself shifted by 1 dot
+    fn emboldened(&self) -> Coverage {
+        Coverage::new(self.origin, self.width + 1, self.height, |x, y| {
+            cmp::max(self.get(x, y), self.get(x - 1, y))
+        })
+    }
+    /// Grow the glyph by `t` dots in all directions(roughly circular)
+    fn dilated(&self, t: i32) -> Coverage {
+        let origin = self.origin.sub((t, t));
+        Coverage::new(origin, self.width + t * 2, self.height + t * 2, |x, y| {
+            let mut max = 0;
+            for dy in -t..=t {
+                for dx in -t..=t {
+                    if dx * dx + dy * dy <= t * t + t {
+                        max = cmp::max(max, self.get(x - t + dx, y - t + dy));
+                    }
+                }
+            }
+            max
+        })
+    }
+    /// Call `f` for each visible dot, moved by `offset`
+    fn dots(
+        &self,
+        color: Color,
+        offset: (i32, i32),
+        f: &mut dyn FnMut((i32, i32), Color, Alpha),
+    ) {
+        let origin = self.origin.add(offset);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let alpha = Alpha(self.get(x, y));
+                if !alpha.is_trans() {
+                    f((x, y).add(origin), color, alpha);
+                }
+            }
         }
     }
 }
@@ -45,76 +397,103 @@ impl<'a> FontHandle<'a> {
             cache: HashMap::new(),
         }
     }
+}
 
-    pub fn draw<B, I>(
-        &mut self,
-        buf: &mut B,
-        c: char,
-        setting: &FontSetting,
-    ) -> Result<(), FontError>
-    where
-        B: GetMut2D<Item = I>,
-        I: Blend,
-    {
-        let glyph = match self.font.glyph(c) {
-            Some(g) => g,
-            None => return Err(FontError::NoFont(c)),
-        };
-        let glyph = glyph.scaled(setting.scale);
-        let glyph = glyph.positioned(setting.start);
-        let mut res = Ok(());
-        let query = (c, setting.scale_u);
-        let scale_u = u32::from(setting.scale_u);
-        let start_u = (setting.start.x, setting.start.y).map(|f| f as u32);
-        match self.cache.entry(query) {
-            Entry::Occupied(o_e) => {
-                let ranges = (setting.start.x, setting.start.y).map(|u| {
-                    let u = u as u32;
-                    u..u + scale_u
-                });
-                let range = RectRange::from_ranges(ranges.0, ranges.1).unwrap();
-                range.into_iter().for_each(|(x, y)| {
-                    let alpha = o_e.get().get(x, y);
-                    let p = (x, y).add(start_u);
-                    match buf.get_mut_point_r(p) {
-                        Ok(b) => b.blend(setting.color, alpha),
-                        Err(id) => res = Err(FontError::Index(id)),
-                    }
-                });
-            }
+/// Glyph of `c`, or None if `c` is mapped to .notdef(glyph 0), i.e. the font doesn't have it
+fn mapped_glyph<'a>(font: &Font<'a>, c: char) -> Option<Glyph<'a>> {
+    let glyph = font.glyph(c)?;
+    if glyph.id().0 == 0 {
+        None
+    } else {
+        Some(glyph)
+    }
+}
+
+impl<'a> TextFont for FontHandle<'a> {
+    fn glyph(&mut self, c: char, scale: u8) -> Option<&FontCache> {
+        let cache = match self.cache.entry((c, scale)) {
+            Entry::Occupied(o_e) => o_e.into_mut(),
             Entry::Vacant(v_e) => {
-                let mut cached_font = FontCache::new(scale_u as usize);
-                if let Some(bbox) = glyph.pixel_bounding_box() {
-                    let offset = (bbox.min.x, bbox.max.y).map(|i| i as u32);
-                    glyph.draw(|x, y, v| {
-                        let mut alpha = Alpha::from_f32(v);
-                        // TODO: parameter fix
-                        if alpha.0 < 5 {
-                            return;
-                        }
-                        alpha.plus(3);
-                        let p = (x, y).add(offset);
-                        match buf.get_mut_point_r(p) {
-                            Ok(b) => b.blend(setting.color, alpha),
-                            Err(id) => res = Err(FontError::Index(id)),
-                        }
-                        let (x, y) = p.sub(start_u);
-                        cached_font.set(x, y, alpha);
-                    });
-                }
-                v_e.insert(cached_font);
+                let glyph = mapped_glyph(&self.font, c)?.scaled(Scale::uniform(f32::from(scale)));
+                v_e.insert(FontCache::rasterize(glyph))
             }
+        };
+        Some(cache)
+    }
+}
+
+impl<'a> FontMetrics for FontHandle<'a> {
+    fn advance(&self, c: char, scale: f32) -> Option<f32> {
+        let glyph = mapped_glyph(&self.font, c)?;
+        Some(glyph.scaled(Scale::uniform(scale)).h_metrics().advance_width)
+    }
+    fn kerning(&self, a: char, b: char, scale: f32) -> f32 {
+        self.font.pair_kerning(Scale::uniform(scale), a, b)
+    }
+    fn line_metrics(&self, scale: f32) -> LineMetrics {
+        let v = self.font.v_metrics(Scale::uniform(scale));
+        LineMetrics {
+            ascent: v.ascent,
+            descent: v.descent,
+            line_gap: v.line_gap,
         }
-        res
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct FontSetting {
-    color: Color,
-    scale: Scale,
-    scale_u: u8,
-    start: Point<f32>,
+    pub(crate) color: Color,
+    pub(crate) scale_u: u8,
+    pub(crate) start: Point<f32>,
+    /// lines longer than this are wrapped by draw_str
+    pub(crate) max_width: Option<u32>,
+    pub(crate) align: Align,
+    pub(crate) effects: TextEffects,
+    /// vertical(tategaki) layout
+    pub(crate) vertical: bool,
+    /// columns longer than this are wrapped in vertical layout
+    pub(crate) max_height: Option<u32>,
+    /// return an error instead of clipping text out of the buffer
+    pub(crate) strict: bool,
+}
+
+/// Effects which make text readable over busy backgrounds
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TextEffects {
+    /// (color, thickness)
+    pub outline: Option<(Color, u8)>,
+    /// (offset, color)
+    pub shadow: Option<((i16, i16), Color)>,
+    /// thicken glyphs by 1 dot
+    pub bold: bool,
+}
+
+impl TextEffects {
+    /// Whether `pass` draws anything
+    fn draws(&self, pass: Pass) -> bool {
+        match pass {
+            Pass::Shadow => self.shadow.is_some(),
+            Pass::Outline => self.outline.is_some(),
+            Pass::Body => true,
+        }
+    }
+    /// `rect` of text grown by the dots effects draw around it
+    pub(crate) fn ink_rect(&self, rect: DotRect) -> DotRect {
+        if rect.is_empty() {
+            return rect;
+        }
+        let mut ink = rect;
+        if self.bold {
+            ink.size.width += 1;
+        }
+        if let Some((_, t)) = self.outline {
+            ink = ink.inflate(i16::from(t), i16::from(t));
+        }
+        if let Some(((dx, dy), _)) = self.shadow {
+            ink = ink.union(&ink.translate(&vec2(dx, dy)));
+        }
+        ink
+    }
 }
 
 impl FontSetting {
@@ -125,13 +504,17 @@ impl FontSetting {
     pub fn new() -> Self {
         FontSetting {
             color: Color::black(),
-            scale: Scale::uniform(TILE_SIZE as f32),
             scale_u: TILE_SIZE as u8,
             start: point(0.0, 0.0),
+            max_width: None,
+            align: Align::Left,
+            effects: TextEffects::default(),
+            vertical: false,
+            max_height: None,
+            strict: false,
         }
     }
     pub fn scale(&mut self, s: u8) -> &mut Self {
-        self.scale = Scale::uniform(f32::from(s));
         self.scale_u = s;
         self
     }
@@ -144,25 +527,140 @@ impl FontSetting {
         self.start = point(x, y);
         self
     }
+    pub fn max_width(&mut self, w: u32) -> &mut Self {
+        self.max_width = Some(w);
+        self
+    }
+    pub fn align(&mut self, a: Align) -> &mut Self {
+        self.align = a;
+        self
+    }
+    /// Lay out text vertically(top to bottom, columns from right to left)
+    pub fn vertical(&mut self, vertical: bool) -> &mut Self {
+        self.vertical = vertical;
+        self
+    }
+    pub fn max_height(&mut self, h: u32) -> &mut Self {
+        self.max_height = Some(h);
+        self
+    }
+    /// Draw outline of `thickness` dots around glyphs
+    pub fn outline(&mut self, color: Color, thickness: u8) -> &mut Self {
+        self.effects.outline = Some((color, thickness));
+        self
+    }
+    /// Draw drop shadow(of the outline, if any) at `offset`
+    pub fn shadow(&mut self, offset: (i16, i16), color: Color) -> &mut Self {
+        self.effects.shadow = Some((offset, color));
+        self
+    }
+    pub fn bold(&mut self, bold: bool) -> &mut Self {
+        self.effects.bold = bold;
+        self
+    }
+    pub fn effects(&mut self, effects: TextEffects) -> &mut Self {
+        self.effects = effects;
+        self
+    }
+    /// Check that the whole text fits in the buffer before drawing, and return
+    /// OutOfRange error if not(by default, dots out of the buffer are clipped)
+    pub fn strict(&mut self, strict: bool) -> &mut Self {
+        self.strict = strict;
+        self
+    }
 }
 
-#[derive(Clone, Debug)]
-struct FontCache {
+/// Coverage bitmap of a glyph, independent of the position it's drawn at
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FontCache {
+    /// 4bit alpha values, 2 dots per byte
     inner: Vec<u8>,
-    scale: usize,
+    width: usize,
+    height: usize,
+    /// offset of the upper left corner of the bitmap from the pen position
+    bearing: (i32, i32),
 }
 
 impl FontCache {
-    fn new(scale: usize) -> FontCache {
-        let len = (scale * scale + 1) / 2;
+    pub(crate) fn new(width: usize, height: usize, bearing: (i32, i32)) -> FontCache {
+        let len = (width * height + 1) / 2;
         FontCache {
             inner: vec![0u8; len],
-            scale: scale,
+            width: width,
+            height: height,
+            bearing: bearing,
+        }
+    }
+    /// Bitmap from nibble-packed alpha values(as returned by `packed`)
+    pub(crate) fn from_packed(
+        width: usize,
+        height: usize,
+        bearing: (i32, i32),
+        inner: Vec<u8>,
+    ) -> Option<FontCache> {
+        if inner.len() != (width * height + 1) / 2 {
+            return None;
         }
+        Some(FontCache {
+            inner: inner,
+            width: width,
+            height: height,
+            bearing: bearing,
+        })
+    }
+    /// Alpha values packed 2 dots per byte(lower 4 bits first)
+    pub(crate) fn packed(&self) -> &[u8] {
+        &self.inner
     }
-    fn set(&mut self, x: u32, y: u32, val: Alpha) {
+    /// Rasterize `glyph` with its pen position at the origin
+    fn rasterize(glyph: ScaledGlyph) -> FontCache {
+        let glyph = glyph.positioned(point(0.0, 0.0));
+        let bbox = match glyph.pixel_bounding_box() {
+            Some(b) => b,
+            None => return FontCache::new(0, 0, (0, 0)),
+        };
+        let (w, h) = (bbox.width(), bbox.height()).map(|i| i as usize);
+        let mut cache = FontCache::new(w, h, (bbox.min.x, bbox.min.y));
+        glyph.draw(|x, y, v| {
+            let mut alpha = Alpha::from_f32(v);
+            // TODO: parameter fix
+            if alpha.0 < 5 {
+                return;
+            }
+            alpha.plus(3);
+            cache.set(x, y, alpha);
+        });
+        cache
+    }
+    /// All points in the bitmap, in row-major order
+    pub(crate) fn points(&self) -> impl Iterator<Item = (u32, u32)> {
+        let (w, h) = (self.width, self.height).map(|u| u as u32);
+        (0..h).flat_map(move |y| (0..w).map(move |x| (x, y)))
+    }
+    /// Magnify the bitmap `k` times(for bitmap fonts)
+    pub(crate) fn magnify(&self, k: u8) -> FontCache {
+        let k = i32::from(k);
+        let (w, h) = (self.width, self.height).map(|u| u * k as usize);
+        let bearing = self.bearing.map(|i| i * k);
+        let mut res = FontCache::new(w, h, bearing);
+        res.points().for_each(|(x, y)| {
+            let (sx, sy) = (x, y).map(|u| u / k as u32);
+            res.set(x, y, self.get(sx, sy));
+        });
+        res
+    }
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn height(&self) -> usize {
+        self.height
+    }
+    pub fn bearing(&self) -> (i32, i32) {
+        self.bearing
+    }
+    pub(crate) fn set(&mut self, x: u32, y: u32, val: Alpha) {
         let (x, y) = (x, y).map(|a| a as usize);
-        let id = self.scale * y + x;
+        let id = self.width * y + x;
         let id2 = id / 2;
         if (id & 1) == 1 {
             self.inner[id2] |= val.0 << 4;
@@ -170,9 +668,9 @@ impl FontCache {
             self.inner[id2] |= val.0;
         };
     }
-    fn get(&self, x: u32, y: u32) -> Alpha {
+    pub fn get(&self, x: u32, y: u32) -> Alpha {
         let (x, y) = (x, y).map(|a| a as usize);
-        let pos = self.scale * y + x;
+        let pos = self.width * y + x;
         let id = pos / 2;
         let val = if (pos & 1) == 1 {
             (self.inner[id] & 0b11110000) >> 4
@@ -183,10 +681,15 @@ impl FontCache {
     }
 }
 
+#[cfg(test)]
 mod font_test {
     use super::*;
+    use bitmap_font::BitmapFont;
+    use euclid::point2;
+    use image::{Rgba, RgbaImage};
     use rect_iter::Get2D;
     use test::Bencher;
+    use text::TextMeasure;
     use tile::Tile;
     const MIGU: &[u8; 3137552] = include_bytes!("../../assets/migu-1m-regular.ttf");
     #[test]
@@ -201,6 +704,14 @@ mod font_test {
         }
     }
 
+    #[test]
+    fn notdef_is_missing() {
+        let mut font = FontHandle::new(&MIGU[..]);
+        assert_eq!(font.advance('\u{1F600}', 16.0), None);
+        assert!(font.glyph('\u{1F600}', 16).is_none());
+        assert!(font.glyph('あ', 16).is_some());
+    }
+
     #[test]
     fn draw_tile_cached() {
         let setting = FontSetting::new();
@@ -218,6 +729,237 @@ mod font_test {
         }
     }
 
+    /// draw `c` with the cache cleared before the 2nd drawing or not
+    fn draw_twice(font: &mut FontHandle, c: char, setting: &FontSetting, clear: bool) -> RgbaImage {
+        let white = Rgba { data: [255; 4] };
+        let mut img = RgbaImage::from_pixel(64, 64, white);
+        font.draw(&mut img, c, setting).unwrap();
+        let mut img = RgbaImage::from_pixel(64, 64, white);
+        if clear {
+            font.cache.clear();
+        }
+        font.draw(&mut img, c, setting).unwrap();
+        img
+    }
+
+    #[test]
+    fn cache_position_independent() {
+        let mut font = FontHandle::new(&MIGU[..]);
+        for &scale in &[8, 12, 16, 24] {
+            for &start in &[(0.0, 0.0), (3.0, 5.0), (17.0, 2.0), (30.0, 31.0), (9.4, 11.6)] {
+                let mut setting = FontSetting::new();
+                setting.scale(scale).start(start);
+                for c in "あ隣gQ.".chars() {
+                    let cached = draw_twice(&mut font, c, &setting, false);
+                    let uncached = draw_twice(&mut font, c, &setting, true);
+                    assert_eq!(cached.into_raw(), uncached.into_raw());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cache_translated() {
+        let white = Rgba { data: [255; 4] };
+        let mut font = FontHandle::new(&MIGU[..]);
+        let mut setting = FontSetting::new();
+        let mut base = RgbaImage::from_pixel(64, 64, white);
+        font.draw(&mut base, 'あ', &setting).unwrap();
+        for &(dx, dy) in &[(1u32, 0u32), (5, 7), (20, 33)] {
+            let mut img = RgbaImage::from_pixel(64, 64, white);
+            setting.start((dx as f32, dy as f32));
+            font.draw(&mut img, 'あ', &setting).unwrap();
+            for (x, y, p) in base.enumerate_pixels() {
+                if x + dx < 64 && y + dy < 64 {
+                    assert_eq!(p, img.get_pixel(x + dx, y + dy));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn draw_str() {
+        let white = Rgba { data: [255; 4] };
+        let mut img = RgbaImage::from_pixel(64, 128, white);
+        let mut setting = FontSetting::new();
+        setting.start((4.0, 4.0)).max_width(48);
+        let mut font = FontHandle::new(&MIGU[..]);
+        let bbox = font.draw_str(&mut img, "隣の客はよく柿食う客だ", &setting).unwrap();
+        assert_eq!(bbox.origin, point2(4, 4));
+        assert!(bbox.size.width <= 48);
+        assert!(bbox.size.height >= 16 * 4);
+        let mut drawn = 0;
+        for (x, y, p) in img.enumerate_pixels() {
+            if p != &white {
+                drawn += 1;
+                assert!(bbox.contains(&point2(x as i16, y as i16)));
+            }
+        }
+        assert!(drawn > 0);
+    }
+
+    fn tiny() -> BitmapFont {
+        BitmapFont::load(::std::path::Path::new("../test-assets/font/tiny.bdf")).unwrap()
+    }
+
+    fn tiny_a(setting: &FontSetting) -> RgbaImage {
+        let mut font = tiny();
+        let mut img = RgbaImage::from_pixel(16, 16, Rgba { data: [255; 4] });
+        font.draw(&mut img, 'A', setting).unwrap();
+        img
+    }
+
+    #[test]
+    fn effects() {
+        let (black, red, blue) = ([0, 0, 0, 255], [255, 0, 0, 255], [0, 0, 255, 255]);
+        let red_c = Color { r: 255, g: 0, b: 0 };
+        let blue_c = Color { r: 0, g: 0, b: 255 };
+        let mut setting = FontSetting::new();
+        setting.scale(8).start((2.0, 2.0));
+        let plain = tiny_a(&setting);
+        assert_eq!(plain.get_pixel(4, 2).data, black);
+        setting.outline(red_c, 1);
+        let img = tiny_a(&setting);
+        assert_eq!(img.get_pixel(4, 2).data, black);
+        assert_eq!(img.get_pixel(4, 1).data, red);
+        assert_eq!(img.get_pixel(4, 3).data, red);
+        assert_eq!(img.get_pixel(4, 0).data, [255; 4]);
+        setting.effects(TextEffects::default()).shadow((1, 1), blue_c);
+        let img = tiny_a(&setting);
+        assert_eq!(img.get_pixel(3, 7).data, blue);
+        assert_eq!(img.get_pixel(7, 7).data, blue);
+        assert_eq!(img.get_pixel(6, 7).data, black);
+        setting.effects(TextEffects::default()).bold(true);
+        let img = tiny_a(&setting);
+        assert_eq!(img.get_pixel(5, 2).data, black);
+        assert_eq!(img.get_pixel(7, 6).data, black);
+        assert_eq!(img.get_pixel(8, 6).data, [255; 4]);
+    }
+
+    #[test]
+    fn effects_over_neighbors() {
+        let (black, red, blue) = ([0, 0, 0, 255], [255, 0, 0, 255], [0, 0, 255, 255]);
+        let red_c = Color { r: 255, g: 0, b: 0 };
+        let blue_c = Color { r: 0, g: 0, b: 255 };
+        let mut font = tiny();
+        let mut setting = FontSetting::new();
+        setting.scale(8).start((2.0, 2.0));
+        let plain = font.measure("AB", &setting).unwrap().bbox;
+        // the right column of 'A' is at x = 6, and the left column of 'B' is at x = 8
+        setting.outline(red_c, 2);
+        let mut img = RgbaImage::from_pixel(32, 16, Rgba { data: [255; 4] });
+        let bbox = font.draw_str(&mut img, "AB", &setting).unwrap();
+        assert_eq!(img.get_pixel(6, 5).data, black);
+        assert_eq!(img.get_pixel(7, 5).data, red);
+        assert_eq!(img.get_pixel(8, 5).data, black);
+        assert_eq!(bbox, plain.inflate(2, 2));
+        assert_eq!(font.measure("AB", &setting).unwrap().bbox, bbox);
+        for (x, y, p) in img.enumerate_pixels() {
+            if p.data != [255; 4] {
+                assert!(bbox.contains(&point2(x as i16, y as i16)));
+            }
+        }
+        setting.effects(TextEffects::default()).shadow((-2, 0), blue_c).bold(true);
+        let mut img = RgbaImage::from_pixel(32, 16, Rgba { data: [255; 4] });
+        let bbox = font.draw_str(&mut img, "AB", &setting).unwrap();
+        assert_eq!(img.get_pixel(7, 5).data, black);
+        assert_eq!(img.get_pixel(5, 5).data, blue);
+        assert_eq!(bbox, rect(0, 2, 15, plain.size.height));
+    }
+
+    #[test]
+    fn effects_on_tile() {
+        let red = Color { r: 255, g: 0, b: 0 };
+        let mut setting = FontSetting::new();
+        setting.scale(8).start((2.0, 2.0)).outline(red, 1).bold(true);
+        let mut font = tiny();
+        let mut tile = Tile::new(Some(Color::white()));
+        font.draw(&mut tile, 'A', &setting).unwrap();
+        assert_eq!(*tile.get_point((4, 1)).unwrap(), Some(red));
+        assert_eq!(*tile.get_point((5, 2)).unwrap(), Some(Color::black()));
+    }
+
+    #[test]
+    fn clip() {
+        let mut setting = FontSetting::new();
+        setting.scale(8).start((2.0, 2.0));
+        let base = tiny_a(&setting);
+        setting.start((-2.0, -3.0));
+        let clipped = tiny_a(&setting);
+        for (x, y, p) in clipped.enumerate_pixels() {
+            if x + 4 < 16 && y + 5 < 16 {
+                assert_eq!(p, base.get_pixel(x + 4, y + 5));
+            }
+        }
+        assert!(clipped.pixels().any(|p| p.data != [255; 4]));
+        // effects are clipped too
+        setting.start((2.0, 2.0)).outline(Color { r: 255, g: 0, b: 0 }, 1);
+        setting.shadow((-1, -1), Color { r: 0, g: 0, b: 255 });
+        let base = tiny_a(&setting);
+        setting.start((-2.0, -3.0));
+        let clipped = tiny_a(&setting);
+        for (x, y, p) in clipped.enumerate_pixels() {
+            if x + 4 < 16 && y + 5 < 16 {
+                assert_eq!(p, base.get_pixel(x + 4, y + 5));
+            }
+        }
+        assert!(clipped.pixels().any(|p| p.data == [255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn strict() {
+        let white = Rgba { data: [255; 4] };
+        let mut font = tiny();
+        let mut img = RgbaImage::from_pixel(16, 16, white);
+        let mut setting = FontSetting::new();
+        setting.scale(8).start((6.0, 2.0)).strict(true);
+        font.draw_str(&mut img, "A", &setting).unwrap();
+        let mut img = RgbaImage::from_pixel(16, 16, white);
+        // the right column of 'B' is at x = 16
+        match font.draw_str(&mut img, "AB", &setting) {
+            Err(FontError::OutOfRange(e)) => {
+                assert_eq!((e.c, e.index, e.dots), ('B', 1, 4));
+                assert_eq!(e.region, rect(16, 3, 1, 5));
+            }
+            r => panic!("{:?}", r),
+        }
+        assert!(img.pixels().all(|p| p == &white));
+        setting.strict(false);
+        font.draw_str(&mut img, "AB", &setting).unwrap();
+        assert!(img.pixels().any(|p| p != &white));
+    }
+
+    #[test]
+    fn draw_vertical() {
+        let white = Rgba { data: [255; 4] };
+        let mut img = RgbaImage::from_pixel(96, 64, white);
+        let mut setting = FontSetting::new();
+        setting.vertical(true).max_height(48);
+        let mut font = FontHandle::new(&MIGU[..]);
+        let bbox = font.draw_str(&mut img, "縦書き、ーです。", &setting).unwrap();
+        assert!(bbox.size.height <= 48);
+        assert!(bbox.size.width >= 3 * 16);
+        assert!(img.pixels().any(|p| p != &white));
+    }
+
+    #[test]
+    fn measure_same_as_draw() {
+        let white = Rgba { data: [255; 4] };
+        let mut font = FontHandle::new(&MIGU[..]);
+        for &vertical in &[false, true] {
+            let mut setting = FontSetting::new();
+            setting.start((3.0, 5.0)).max_width(60).max_height(60).vertical(vertical);
+            setting.outline(Color::white(), 1);
+            let s = "隣の客はよく柿食う客だ。\nabc d ";
+            let m = font.measure(s, &setting).unwrap();
+            let mut img = RgbaImage::from_pixel(128, 128, white);
+            let bbox = font.draw_str(&mut img, s, &setting).unwrap();
+            assert_eq!(m.bbox, bbox);
+            assert_eq!(m.chars.len(), s.chars().count());
+            assert!(m.chars.iter().all(|&(_, cell)| bbox.contains_rect(&cell)));
+        }
+    }
+
     #[bench]
     fn no_cache(b: &mut Bencher) {
         let setting = FontSetting::new();
@@ -0,0 +1,152 @@
+//! font fallback chain
+
+use font::{FontCache, TextFont};
+use text::{FontMetrics, LineMetrics};
+
+/// Fonts tried in order for each character, e.g. a pixel latin font, then
+/// Migu for kana and kanji, then a symbol font.
+/// Characters no font has are drawn with the replacement glyph.
+pub struct FontStack<'a> {
+    fonts: Vec<Box<dyn TextFont + 'a>>,
+    replacement: Option<char>,
+}
+
+impl<'a> FontStack<'a> {
+    pub fn new() -> FontStack<'a> {
+        FontStack {
+            fonts: Vec::new(),
+            replacement: Some('?'),
+        }
+    }
+    /// Add a font with lower priority than fonts already pushed
+    pub fn push<F: TextFont + 'a>(&mut self, font: F) -> &mut Self {
+        self.fonts.push(Box::new(font));
+        self
+    }
+    /// Character drawn instead of missing ones(None to return NoFont error)
+    pub fn replacement(&mut self, c: Option<char>) -> &mut Self {
+        self.replacement = c;
+        self
+    }
+    pub fn len(&self) -> usize {
+        self.fonts.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.fonts.is_empty()
+    }
+    fn find(&self, c: char, scale: f32) -> Option<usize> {
+        self.fonts.iter().position(|f| f.advance(c, scale).is_some())
+    }
+    /// (index of the font, character to draw) for `c`
+    fn resolve(&self, c: char, scale: f32) -> Option<(usize, char)> {
+        if let Some(i) = self.find(c, scale) {
+            return Some((i, c));
+        }
+        let r = self.replacement?;
+        self.find(r, scale).map(|i| (i, r))
+    }
+}
+
+impl<'a> FontMetrics for FontStack<'a> {
+    fn advance(&self, c: char, scale: f32) -> Option<f32> {
+        let (i, c) = self.resolve(c, scale)?;
+        self.fonts[i].advance(c, scale)
+    }
+    fn kerning(&self, a: char, b: char, scale: f32) -> f32 {
+        match (self.resolve(a, scale), self.resolve(b, scale)) {
+            (Some((i, a)), Some((j, b))) if i == j => self.fonts[i].kerning(a, b, scale),
+            _ => 0.0,
+        }
+    }
+    /// Lines are tall enough for all fonts
+    fn line_metrics(&self, scale: f32) -> LineMetrics {
+        let init = LineMetrics {
+            ascent: 0.0,
+            descent: 0.0,
+            line_gap: 0.0,
+        };
+        self.fonts.iter().fold(init, |acc, f| {
+            let m = f.line_metrics(scale);
+            LineMetrics {
+                ascent: acc.ascent.max(m.ascent),
+                descent: acc.descent.min(m.descent),
+                line_gap: acc.line_gap.max(m.line_gap),
+            }
+        })
+    }
+}
+
+impl<'a> TextFont for FontStack<'a> {
+    fn glyph(&mut self, c: char, scale: u8) -> Option<&FontCache> {
+        let (i, c) = self.resolve(c, f32::from(scale))?;
+        self.fonts[i].glyph(c, scale)
+    }
+}
+
+#[cfg(test)]
+mod font_stack_test {
+    use super::*;
+    use bitmap_font::BitmapFont;
+    use font::{FontError, FontHandle, FontSetting, TextDraw};
+    use image::{Rgba, RgbaImage};
+    use std::path::Path;
+    const MIGU: &[u8; 3137552] = include_bytes!("../../assets/migu-1m-regular.ttf");
+    fn stack() -> FontStack<'static> {
+        let tiny = BitmapFont::load(Path::new("../test-assets/font/tiny.bdf")).unwrap();
+        let mut buf = RgbaImage::from_pixel(16, 8, Rgba { data: [0; 4] });
+        buf.put_pixel(0, 0, Rgba { data: [0, 0, 0, 255] });
+        let grid = BitmapFont::from_grid(&buf, "8 8 7\n?C").unwrap();
+        let mut stack = FontStack::new();
+        stack.push(tiny).push(grid);
+        stack
+    }
+    #[test]
+    fn fallback() {
+        let mut stack = stack();
+        let mut setting = FontSetting::new();
+        setting.scale(8);
+        let mut img = RgbaImage::from_pixel(32, 16, Rgba { data: [255; 4] });
+        // 'A' from tiny.bdf(6 dots), 'C' from the grid(8 dots)
+        let bbox = stack.draw_str(&mut img, "AC", &setting).unwrap();
+        assert_eq!(bbox.size.width, 14);
+        assert_eq!(stack.advance('Z', 8.0), Some(8.0));
+        stack.draw_str(&mut img, "AZ", &setting).unwrap();
+        stack.replacement(None);
+        match stack.draw_str(&mut img, "AZ", &setting) {
+            Err(FontError::NoFont('Z')) => {}
+            r => panic!("{:?}", r),
+        }
+    }
+    #[test]
+    fn handle_in_the_middle() {
+        // Migu doesn't have U+1F600, so it falls back to the grid font
+        let mut buf = RgbaImage::from_pixel(8, 8, Rgba { data: [0; 4] });
+        buf.put_pixel(0, 0, Rgba { data: [0, 0, 0, 255] });
+        let grid = BitmapFont::from_grid(&buf, "8 8 7\n\u{1F600}").unwrap();
+        let tiny = BitmapFont::load(Path::new("../test-assets/font/tiny.bdf")).unwrap();
+        let mut stack = FontStack::new();
+        stack.push(tiny).push(FontHandle::new(&MIGU[..])).push(grid);
+        assert_eq!(stack.resolve('A', 16.0), Some((0, 'A')));
+        assert_eq!(stack.resolve('あ', 16.0), Some((1, 'あ')));
+        assert_eq!(stack.resolve('\u{1F600}', 16.0), Some((2, '\u{1F600}')));
+        // no font has U+1F601, so Migu's '?' is drawn
+        assert_eq!(stack.resolve('\u{1F601}', 16.0), Some((1, '?')));
+        let mut img = RgbaImage::from_pixel(64, 32, Rgba { data: [255; 4] });
+        stack.draw_str(&mut img, "A\u{1F600}\u{1F601}", &FontSetting::new()).unwrap();
+        let mut stack = FontStack::new();
+        stack.push(FontHandle::new(&MIGU[..])).replacement(None);
+        match stack.draw_str(&mut img, "\u{1F600}", &FontSetting::new()) {
+            Err(FontError::NoFont('\u{1F600}')) => {}
+            r => panic!("{:?}", r),
+        }
+    }
+    #[test]
+    fn mixed_script() {
+        let mut stack = stack();
+        stack.push(FontHandle::new(&MIGU[..]));
+        let setting = FontSetting::new();
+        let mut img = RgbaImage::from_pixel(64, 32, Rgba { data: [255; 4] });
+        stack.draw_str(&mut img, "AあB漢", &setting).unwrap();
+        assert_eq!(stack.len(), 3);
+    }
+}
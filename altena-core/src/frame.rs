@@ -3,16 +3,19 @@
 use euclid::{point2, rect, vec2, TypedRect, TypedVector2D};
 use image::{Rgba, RgbaImage};
 use num_traits::{Num, ToPrimitive};
-use rect_iter::{copy_rect, copy_rect_conv, gen_rect_conv, Get2D, IntoTuple2, RectRange};
+use rect_iter::{copy_rect, copy_rect_conv, gen_rect_conv, Get2D, GetMut2D, IntoTuple2, RectRange};
 use tuple_map::TupleMap2;
 
 use std::cmp::{max, min};
+use std::error::Error;
 use std::fmt;
 use std::ops::Range;
 use std::slice;
 
+use collision::{LayerMatrix, LayerPairs};
+use primitive::buf_size;
 use tile::tiletypes::*;
-use tile::{Alpha, AltenaAlpha, Color, Dot, Tile};
+use tile::{blit_tile, Alpha, AltenaAlpha, Blend, Color, Dot, Tile};
 
 pub mod dottypes {
     use euclid::*;
@@ -42,6 +45,33 @@ impl ToDotVec for DotVector {
     }
 }
 
+/// Blend tiles of a frame into `buf` with its upper left corner at `origin`.
+/// `color_at` returns the color of (tile, dot in the tile, dot in `buf`).
+pub(crate) fn blit_frame<T, B, I, F>(
+    buf: &mut B,
+    tiles: &[(T, TilePoint)],
+    origin: DotPoint,
+    alpha: Alpha,
+    color_at: F,
+) where
+    B: GetMut2D<Item = I>,
+    I: Blend,
+    F: Fn(&T, (usize, usize), (i16, i16)) -> Option<Color>,
+{
+    let (w, h) = buf_size(buf);
+    let len = TILE_SIZE as i32;
+    for (tile, point) in tiles {
+        // computed in i32, since large frames or far origins overflow i16
+        let x = i32::from(origin.x) + i32::from(point.x) * len;
+        let y = i32::from(origin.y) + i32::from(point.y) * len;
+        // the whole tile is out of buf
+        if x + len <= 0 || y + len <= 0 || x >= i32::from(w) || y >= i32::from(h) {
+            continue;
+        }
+        blit_tile(buf, x as i16, y as i16, alpha, |p, q| color_at(tile, p, q));
+    }
+}
+
 /// Slide ractangle(ract.origin += offset).
 fn slide_rect<T: Num + Copy, P: IntoTuple2<T>, Unit>(
     rect: TypedRect<T, Unit>,
@@ -59,7 +89,8 @@ fn tile_size() -> DotSize {
     DotSize::new(TILE_SIZE as i16, TILE_SIZE as i16)
 }
 
-fn tile_num(len: usize) -> usize {
+/// Number of tiles needed to cover `len` dots
+pub(crate) fn tile_num(len: usize) -> usize {
     (len + TILE_SIZE - 1) / TILE_SIZE
 }
 
@@ -137,14 +168,159 @@ impl TileDir {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameError {
+    /// (width, height) of the image exceeds DotSpace
+    TooLarge(u32, u32),
+    /// the image has no dots
+    Empty,
+    /// the image has no dots with collision bits
+    NoMesh,
+}
+
+impl Error for FrameError {
+    fn description(&self) -> &str {
+        "Frame Error"
+    }
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            FrameError::TooLarge(w, h) => write!(f, "TooLarge: {}×{}", w, h),
+            FrameError::Empty => write!(f, "Empty"),
+            FrameError::NoMesh => write!(f, "NoMesh"),
+        }
+    }
+}
+
+/// Images must fit in DotSpace(i16)
+fn check_size(w: u32, h: u32) -> Result<(), FrameError> {
+    let max_len = i16::max_value() as u32;
+    if w > max_len || h > max_len {
+        return Err(FrameError::TooLarge(w, h));
+    }
+    if w == 0 || h == 0 {
+        return Err(FrameError::Empty);
+    }
+    Ok(())
+}
+
+/// Point at `t` on the segment `path`, rounded to the nearest dot
+fn lerp_point(path: &Range<DotPoint>, t: f32) -> DotPoint {
+    let d = path.end - path.start;
+    let (x, y) = ((path.start.x, d.x), (path.start.y, d.y))
+        .map(|(start, len)| start + (f32::from(len) * t).round() as i16);
+    point2(x, y)
+}
+
+/// floor((a / b) + 0.5) for b > 0
+fn round_div(a: i32, b: i32) -> i32 {
+    let (n, m) = (2 * a + b, 2 * b);
+    if n >= 0 {
+        n / m
+    } else {
+        (n - m + 1) / m
+    }
+}
+
+/// `i`th dot(0 <= i <= len) of the line `start`→`start + d`
+fn line_dot(start: DotPoint, d: DotVector, len: i32, i: i32) -> DotPoint {
+    if len == 0 {
+        return start;
+    }
+    let step = |s: i16, d: i16| s + round_div(i32::from(d) * i, len) as i16;
+    point2(step(start.x, d.x), step(start.y, d.y))
+}
+
+/// Conservative range of steps whose dots may be inside `rect`
+fn line_range_in(start: DotPoint, d: DotVector, len: i32, rect: DotRect) -> Option<(i32, i32)> {
+    let axis = |s: i16, d: i16, min_edge: i16, max_edge: i16| {
+        if d == 0 {
+            return if min_edge <= s && s < max_edge {
+                Some((0, len))
+            } else {
+                None
+            };
+        }
+        let to_step = |p: i16| f32::from(p - s) * len as f32 / f32::from(d);
+        let (a, b) = (to_step(min_edge - 1), to_step(max_edge));
+        let (a, b) = if a < b { (a, b) } else { (b, a) };
+        Some((a.floor() as i32, b.ceil() as i32))
+    };
+    let x = axis(start.x, d.x, rect.min_x(), rect.max_x())?;
+    let y = axis(start.y, d.y, rect.min_y(), rect.max_y())?;
+    let (lo, hi) = (max(max(x.0, y.0), 0), min(min(x.1, y.1), len));
+    if lo <= hi {
+        Some((lo, hi))
+    } else {
+        None
+    }
+}
+
+/// Result of collision detection
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Contact {
+    /// intersection of bounding boxes where meshes overlap
+    pub rect: DotRect,
+    /// collision attribute bits of self, which hit the other
+    pub bits: u8,
+    /// pairs of (layer of self, layer of the other) which hit
+    pub pairs: LayerPairs,
+}
+
+impl Contact {
+    fn new(rect: DotRect, pairs: LayerPairs) -> Contact {
+        Contact {
+            rect: rect,
+            bits: pairs.bits_s(),
+            pairs: pairs,
+        }
+    }
+    /// Contact seen from the other side
+    fn swap(self) -> Contact {
+        Contact::new(self.rect, self.pairs.transpose())
+    }
+    /// Fold contacts of all children which hit(rects, bits and pairs are merged)
+    fn merge(acc: Option<Contact>, c: Contact) -> Option<Contact> {
+        Some(match acc {
+            Some(a) => Contact::new(a.rect.union(&c.rect), a.pairs | c.pairs),
+            None => c,
+        })
+    }
+}
+
+/// Result of swept collision detection
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sweep {
+    /// fraction of the movement(0.0..=1.0) at the first contact
+    pub time: f32,
+    /// position of self at the first contact
+    pub pos_s: DotPoint,
+    /// position of other at the first contact
+    pub pos_o: DotPoint,
+    pub contact: Contact,
+}
+
+/// Result of raycast
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RayHit {
+    /// the first dot hit by the ray
+    pub point: DotPoint,
+    /// distance from the start of the ray
+    pub distance: f32,
+    /// collision bits of the dot(masked)
+    pub bits: u8,
+}
+
 /// Leaf for MeshTree
 #[derive(Copy, Clone)]
 pub struct MeshLeaf {
     /// Object Mesh scaled to tile size.
-    inner: [u64; TILE_SIZE],
+    pub(crate) inner: [u64; TILE_SIZE],
     /// Bounding Box of meshed object.
     /// Its origin is based on upper left corner of tile.
-    bbox: DotRect,
+    pub(crate) bbox: DotRect,
 }
 
 impl fmt::Debug for MeshLeaf {
@@ -163,7 +339,8 @@ impl MeshLeaf {
         other: &MeshLeaf,
         offset_s: DotPoint,
         offset_o: DotPoint,
-    ) -> Option<DotRect> {
+        matrix: &LayerMatrix,
+    ) -> Option<Contact> {
         let line_mask = |range: &Range<i16>| {
             let len = (range.end - range.start) as usize;
             let mask = u64::max_value() << ((TILE_SIZE - len) * 4);
@@ -174,22 +351,26 @@ impl MeshLeaf {
         let range_s = get_tile_range(intersect, offset_s)?;
         let range_o = get_tile_range(intersect, offset_o)?;
         let (mask_s, mask_o) = (range_s.get_x(), range_o.get_x()).map(line_mask);
-        range_s
-            .cloned_y()
-            .zip(range_o.cloned_y())
-            .find(|&(y_s, y_o)| {
+        let pairs = range_s.cloned_y().zip(range_o.cloned_y()).fold(
+            LayerPairs::default(),
+            |acc, (y_s, y_o)| {
                 let masked_s = mask_s(self.inner[y_s as usize]);
                 let masked_o = mask_o(other.inner[y_o as usize]);
-                (masked_s & masked_o) != 0
-            })
-            .map(|_| intersect)
+                acc | matrix.hits(masked_s, masked_o)
+            },
+        );
+        if pairs.is_empty() {
+            return None;
+        }
+        Some(Contact::new(intersect, pairs))
     }
     fn collide_n(
         &self,
         other: &MeshNode,
         offset_s: DotPoint,
         offset_o: DotPoint,
-    ) -> Option<DotRect> {
+        matrix: &LayerMatrix,
+    ) -> Option<Contact> {
         if !bbox_intersects(self.bbox, other.bbox, offset_s, offset_o) {
             return None;
         }
@@ -204,19 +385,20 @@ impl MeshLeaf {
                         if !bbox_intersects(self.bbox, leaf.bbox, offset_s, offset_o) {
                             return None;
                         }
-                        self.collide_l(leaf, offset_s, offset_o)
+                        self.collide_l(leaf, offset_s, offset_o, matrix)
                     }
                     MeshTree::Node(node) => {
                         if !bbox_intersects(self.bbox, node.bbox, offset_s, offset_o) {
                             return None;
                         }
-                        self.collide_n(node, offset_s, offset_o)
+                        self.collide_n(node, offset_s, offset_o, matrix)
                     }
                 }
             })
-            .nth(0)
+            .fold(None, Contact::merge)
     }
     fn from_buf(buf: &RgbaImage, range: RectRange<u32>) -> Option<MeshLeaf> {
+        let (ox, oy) = (range.get_x().start, range.get_y().start);
         let (mut min_x, mut min_y, mut max_x, mut max_y) = (TILE_SIZE, TILE_SIZE, 0, 0);
         let mut upd_minmax = |x, y| {
             min_x = min(min_x, x);
@@ -224,9 +406,10 @@ impl MeshLeaf {
             max_x = max(max_x, x);
             max_y = max(max_y, y);
         };
-        let inner = tile_rect().into_iter().zip(range).fold(
+        let inner = range.into_iter().fold(
             [0u64; TILE_SIZE],
-            |mut array, ((x, y), (buf_x, buf_y))| {
+            |mut array, (buf_x, buf_y)| {
+                let (x, y) = ((buf_x - ox) as usize, (buf_y - oy) as usize);
                 let p = buf.get_pixel(buf_x, buf_y);
                 let collision_bits = p.collision_bits();
                 if collision_bits != 0 {
@@ -244,24 +427,48 @@ impl MeshLeaf {
                 .to_rect(),
         })
     }
+    /// Collision bits of the dot at `p`(relative to the upper left corner of the tile)
+    fn bits_at(&self, p: DotPoint) -> u8 {
+        if !self.bbox.contains(&p) {
+            return 0;
+        }
+        let shift = (TILE_SIZE - p.x as usize - 1) * 4;
+        ((self.inner[p.y as usize] >> shift) & 0xf) as u8
+    }
+    /// Leaf filled with `bits`, used for tiles without their own mesh
+    fn solid(bits: u8) -> MeshLeaf {
+        let bits = u64::from(bits & 0b00001111);
+        let row = (0..TILE_SIZE).fold(0u64, |row, _| row << 4 | bits);
+        MeshLeaf {
+            inner: [row; TILE_SIZE],
+            bbox: DotRect::new(point2(0, 0), tile_size()),
+        }
+    }
     fn get_debug_buf(&self) -> Vec<Vec<u64>> {
         self.inner.iter().map(|&u| vec![u]).collect()
     }
 }
 
 /// Node for MeshTree
+#[derive(Clone)]
 pub struct MeshNode {
-    inner: Vec<(MeshTree, TileDir)>,
-    bbox: DotRect,
-    scale: i16,
+    pub(crate) inner: Vec<(MeshTree, TileDir)>,
+    pub(crate) bbox: DotRect,
+    pub(crate) scale: i16,
 }
 
 impl MeshNode {
-    fn collide(&self, other: &MeshNode, offset_s: DotPoint, offset_o: DotPoint) -> Option<DotRect> {
+    fn collide(
+        &self,
+        other: &MeshNode,
+        offset_s: DotPoint,
+        offset_o: DotPoint,
+        matrix: &LayerMatrix,
+    ) -> Option<Contact> {
         if !bbox_intersects(self.bbox, other.bbox, offset_s, offset_o) {
             return None;
         }
-        let compensate = |v: &TileDir| v.to_vec() * self.scale * TILE_SIZE as i16;
+        let compensate = |v: &TileDir| v.to_vec() * self.scale * (TILE_SIZE / 2) as i16;
         self.inner
             .iter()
             .filter_map(|(child_s, dir)| {
@@ -271,17 +478,28 @@ impl MeshNode {
                         if !bbox_intersects(leaf.bbox, other.bbox, offset_s, offset_o) {
                             return None;
                         }
-                        leaf.collide_n(other, offset_s, offset_o)
+                        leaf.collide_n(other, offset_s, offset_o, matrix)
                     }
                     MeshTree::Node(node) => {
                         if !bbox_intersects(node.bbox, other.bbox, offset_s, offset_o) {
                             return None;
                         }
-                        node.collide(other, offset_s, offset_o)
+                        node.collide(other, offset_s, offset_o, matrix)
                     }
                 }
             })
-            .nth(0)
+            .fold(None, Contact::merge)
+    }
+    fn bits_at(&self, p: DotPoint) -> u8 {
+        if !self.bbox.contains(&p) {
+            return 0;
+        }
+        let half = self.scale * (TILE_SIZE / 2) as i16;
+        self.inner
+            .iter()
+            .map(|(child, dir)| child.bits_at(p - dir.to_vec() * half))
+            .find(|&bits| bits != 0)
+            .unwrap_or(0)
     }
     fn get_debug_buf(&self) -> Vec<Vec<u64>> {
         let uscale = self.scale as usize;
@@ -332,7 +550,7 @@ impl fmt::Debug for MeshNode {
 }
 
 /// Utility type for collision detection
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum MeshTree {
     Leaf(MeshLeaf),
     Node(MeshNode),
@@ -340,50 +558,163 @@ pub enum MeshTree {
 
 impl MeshTree {
     /// Detect Collision
-    fn collide(&self, other: &MeshTree, offset_s: DotPoint, offset_o: DotPoint) -> Option<DotRect> {
+    pub(crate) fn collide(
+        &self,
+        other: &MeshTree,
+        offset_s: DotPoint,
+        offset_o: DotPoint,
+    ) -> Option<Contact> {
+        self.collide_with(other, offset_s, offset_o, &LayerMatrix::default())
+    }
+    /// Detect Collision between layers allowed by `matrix`
+    pub(crate) fn collide_with(
+        &self,
+        other: &MeshTree,
+        offset_s: DotPoint,
+        offset_o: DotPoint,
+        matrix: &LayerMatrix,
+    ) -> Option<Contact> {
         match self {
             MeshTree::Leaf(leaf_s) => match other {
-                MeshTree::Leaf(leaf_o) => leaf_s.collide_l(leaf_o, offset_s, offset_o),
-                MeshTree::Node(node_o) => leaf_s.collide_n(node_o, offset_s, offset_o),
+                MeshTree::Leaf(leaf_o) => leaf_s.collide_l(leaf_o, offset_s, offset_o, matrix),
+                MeshTree::Node(node_o) => leaf_s.collide_n(node_o, offset_s, offset_o, matrix),
             },
             MeshTree::Node(node_s) => match other {
-                MeshTree::Leaf(leaf_o) => leaf_o.collide_n(node_s, offset_o, offset_s),
-                MeshTree::Node(node_o) => node_s.collide(node_o, offset_s, offset_o),
+                MeshTree::Leaf(leaf_o) => leaf_o
+                    .collide_n(node_s, offset_o, offset_s, &matrix.transpose())
+                    .map(Contact::swap),
+                MeshTree::Node(node_o) => node_s.collide(node_o, offset_s, offset_o, matrix),
             },
         }
     }
-    fn from_buf_(buf: &RgbaImage, range_orig: RectRange<u32>) -> Option<MeshTree> {
-        let get_scale = |max_len: u32| {
-            let mut len = TILE_SIZE as u32;
-            for scale in 1..6 {
-                if max_len <= len {
-                    return scale;
+    /// Collision bits of the dot at `p`(relative to the upper left corner)
+    pub(crate) fn bits_at(&self, p: DotPoint) -> u8 {
+        match self {
+            MeshTree::Leaf(leaf) => leaf.bits_at(p),
+            MeshTree::Node(node) => node.bits_at(p),
+        }
+    }
+    /// Call `f` with every dot which has collision bits, slid by `offset`
+    pub(crate) fn for_each_dot<F: FnMut(DotPoint, u8)>(&self, offset: DotPoint, f: &mut F) {
+        match self {
+            MeshTree::Leaf(leaf) => {
+                let range = match RectRange::from_rect(leaf.bbox) {
+                    Some(r) => r,
+                    None => return,
+                };
+                for (x, y) in range {
+                    let bits = leaf.bits_at(point2(x, y));
+                    if bits != 0 {
+                        f(point2(offset.x + x, offset.y + y), bits);
+                    }
                 }
-                len *= 2;
             }
-            panic!("Mesh size {} is too big and not supported!", len)
-        };
-        let (xlen, ylen) = (range_orig.xlen(), range_orig.ylen());
-        let scale = get_scale(max(xlen, ylen));
+            MeshTree::Node(node) => {
+                let half = node.scale * (TILE_SIZE / 2) as i16;
+                for (child, dir) in &node.inner {
+                    child.for_each_dot(offset + dir.to_vec() * half, f);
+                }
+            }
+        }
+    }
+    /// Call `f` with bboxes of all nodes and leaves, slid by `offset`
+    pub(crate) fn for_each_bbox<F: FnMut(DotRect)>(&self, offset: DotPoint, f: &mut F) {
+        f(slide_rect(self.bbox(), offset));
+        if let MeshTree::Node(node) = self {
+            let half = node.scale * (TILE_SIZE / 2) as i16;
+            for (child, dir) in &node.inner {
+                child.for_each_bbox(offset + dir.to_vec() * half, f);
+            }
+        }
+    }
+    /// Cast a ray from `start` to `end`(inclusive) against the mesh placed at `offset`.
+    /// Only dots which have some of `mask` bits are hit.
+    pub fn raycast(
+        &self,
+        offset: DotPoint,
+        start: DotPoint,
+        end: DotPoint,
+        mask: u8,
+    ) -> Option<RayHit> {
+        let d = end - start;
+        let len = max(i32::from(d.x).abs(), i32::from(d.y).abs());
+        // skip the dots out of the bbox
+        let bbox = slide_rect(self.bbox(), offset);
+        let (lo, hi) = line_range_in(start, d, len, bbox)?;
+        (lo..=hi)
+            .filter_map(|i| {
+                let p = line_dot(start, d, len, i);
+                let bits = self.bits_at(p - offset.to_vector()) & mask;
+                if bits == 0 {
+                    return None;
+                }
+                let (vx, vy) = (p.x - start.x, p.y - start.y).map(f32::from);
+                Some(RayHit {
+                    point: p,
+                    distance: (vx * vx + vy * vy).sqrt(),
+                    bits: bits,
+                })
+            })
+            .next()
+    }
+    /// Detect the first contact while self moves along `path_s` and other moves along `path_o`.
+    /// The relative movement is divided into steps of at most 1 dot, so thin meshes
+    /// can't be tunneled through.
+    pub(crate) fn sweep(
+        &self,
+        other: &MeshTree,
+        path_s: Range<DotPoint>,
+        path_o: Range<DotPoint>,
+        matrix: &LayerMatrix,
+    ) -> Option<Sweep> {
+        // position of other relative to self
+        let rel_start = path_o.start - path_s.start.to_vector();
+        let rel = rel_start..(path_o.end - path_s.end.to_vector());
+        // fast rejection by the bbox swept along the relative path
+        let swept = slide_rect(other.bbox(), rel.start).union(&slide_rect(other.bbox(), rel.end));
+        if !self.bbox().intersects(&swept) {
+            return None;
+        }
+        let moved = rel.end - rel.start;
+        let steps = max(max(moved.x.abs(), moved.y.abs()), 1);
+        (0..=steps)
+            .filter_map(|i| {
+                let t = f32::from(i) / f32::from(steps);
+                let pos_s = lerp_point(&path_s, t);
+                let pos_o = pos_s + lerp_point(&rel, t).to_vector();
+                let contact = self.collide_with(other, pos_s, pos_o, matrix)?;
+                Some(Sweep {
+                    time: t,
+                    pos_s: pos_s,
+                    pos_o: pos_o,
+                    contact: contact,
+                })
+            })
+            .next()
+    }
+    /// Construct the subtree which covers `scale`×`scale` tiles from `origin`.
+    /// `range` is the part of the square inside the image.
+    fn from_buf_(
+        buf: &RgbaImage,
+        range: RectRange<u32>,
+        origin: (u32, u32),
+        scale: u32,
+    ) -> Option<MeshTree> {
         if scale == 1 {
-            let leaf = MeshLeaf::from_buf(buf, range_orig)?;
+            let leaf = MeshLeaf::from_buf(buf, range)?;
             return Some(MeshTree::Leaf(leaf));
         }
         let mut bbox_res: Option<DotRect> = None;
         let child_scale = scale / 2;
+        let child_len = child_scale * TILE_SIZE as u32;
         let children = TileDir::variants()
             .filter_map(|dir| {
-                let left_up: TypedVector2D<_, DotSpace> =
-                    dir.to_vec() * child_scale * TILE_SIZE as u32;
-                let right_down = left_up + vec2(1, 1) * child_scale * TILE_SIZE as u32;
-                let divided = RectRange::from_corners(left_up, right_down).unwrap();
-                let inter = range_orig.intersection(&divided)?;
-                let res = MeshTree::from_buf_(buf, inter)?;
-                let bbox = match res {
-                    MeshTree::Leaf(ref leaf) => leaf.bbox,
-                    MeshTree::Node(ref node) => node.bbox,
-                };
-                let bbox = slide_rect(bbox, (left_up.x as i16, left_up.y as i16));
+                let left_up: TypedVector2D<u32, DotSpace> = dir.to_vec() * child_len;
+                let (cx, cy) = (origin.0 + left_up.x, origin.1 + left_up.y);
+                let divided = RectRange::new(cx, cy, cx + child_len, cy + child_len)?;
+                let inter = range.intersection(&divided)?;
+                let res = MeshTree::from_buf_(buf, inter, (cx, cy), child_scale)?;
+                let bbox = slide_rect(res.bbox(), (left_up.x as i16, left_up.y as i16));
                 bbox_res = match bbox_res {
                     Some(b) => Some(b.union(&bbox)),
                     None => Some(bbox),
@@ -399,10 +730,24 @@ impl MeshTree {
         Some(MeshTree::Node(res))
     }
     /// construct mesh from Image Buffer
-    pub fn from_buf(buf: &RgbaImage) -> Option<MeshTree> {
-        let (h, w) = (buf.height(), buf.width());
-        let range = RectRange::zero_start(w, h).unwrap();
-        Self::from_buf_(buf, range)
+    pub fn from_buf(buf: &RgbaImage) -> Result<MeshTree, FrameError> {
+        let (w, h) = (buf.width(), buf.height());
+        check_size(w, h)?;
+        let range = RectRange::zero_start(w, h).ok_or(FrameError::Empty)?;
+        // the root node covers scale×scale tiles, where scale is a power of 2
+        let mut scale = 1;
+        while scale * (TILE_SIZE as u32) < max(w, h) {
+            scale *= 2;
+        }
+        Self::from_buf_(buf, range, (0, 0), scale).ok_or(FrameError::NoMesh)
+    }
+    /// construct mesh from a tile in Image Buffer
+    pub(crate) fn from_tile_buf(buf: &RgbaImage, range: RectRange<u32>) -> Option<MeshTree> {
+        MeshLeaf::from_buf(buf, range).map(MeshTree::Leaf)
+    }
+    /// Mesh which covers the whole tile with `bits`
+    pub(crate) fn solid_tile(bits: u8) -> MeshTree {
+        MeshTree::Leaf(MeshLeaf::solid(bits))
     }
 
     fn get_debug_buf(&self) -> Vec<Vec<u64>> {
@@ -412,7 +757,8 @@ impl MeshTree {
         }
     }
 
-    fn bbox(&self) -> DotRect {
+    /// Bounding box of the mesh, based on its upper left corner
+    pub fn bbox(&self) -> DotRect {
         match self {
             MeshTree::Node(node) => node.bbox,
             MeshTree::Leaf(leaf) => leaf.bbox,
@@ -423,15 +769,15 @@ impl MeshTree {
 /// 1 Frame of sprite
 pub struct Frame {
     /// name of Frame
-    name: String,
+    pub(crate) name: String,
     /// collision
-    mesh: MeshTree,
+    pub(crate) mesh: MeshTree,
     /// for drawing
-    tiles: Vec<(Tile, TilePoint)>,
-    alpha: Alpha,
+    pub(crate) tiles: Vec<(Tile, TilePoint)>,
+    pub(crate) alpha: Alpha,
     /// to restore png image
-    w_orig: usize,
-    h_orig: usize,
+    pub(crate) w_orig: usize,
+    pub(crate) h_orig: usize,
 }
 
 impl fmt::Debug for Frame {
@@ -451,9 +797,11 @@ impl fmt::Debug for Frame {
 }
 
 impl Frame {
-    pub fn from_buf(buf: &RgbaImage, name: &str) -> Option<Frame> {
+    pub fn from_buf(buf: &RgbaImage, name: &str) -> Result<Frame, FrameError> {
+        check_size(buf.width(), buf.height())?;
         let (h, w) = (buf.height(), buf.width()).map(|u| u as usize);
-        let tiles: Vec<_> = RectRange::zero_start(tile_num(w), tile_num(h))?
+        let tiles: Vec<_> = RectRange::zero_start(tile_num(w), tile_num(h))
+            .ok_or(FrameError::Empty)?
             .slide((1, 1))
             .into_iter()
             .map(|(tile_x, tile_y)| {
@@ -463,14 +811,14 @@ impl Frame {
                 let tile =
                     gen_rect_conv(buf, Tile::default, buf_rect, tile_rect(), Color::from_rgba)
                         .expect("Index bug in Frame::frame_buf!!!");
-                let tile_p = point2(tile_x as u8 - 1, tile_y as u8 - 1);
+                let tile_p = point2(tile_x as u16 - 1, tile_y as u16 - 1);
                 (tile, tile_p)
             })
             .collect();
         let mesh = MeshTree::from_buf(buf)?;
         let alpha = buf.pixels()
             .fold(Alpha(0), |mut alpha, p| *alpha.max(p.alpha()));
-        Some(Frame {
+        Ok(Frame {
             name: name.to_owned(),
             mesh: mesh,
             tiles: tiles,
@@ -480,6 +828,18 @@ impl Frame {
         })
     }
 
+    /// Draw the frame into `buf` with its upper left corner at `origin`.
+    /// Transparent dots are skipped, and dots out of `buf` are clipped.
+    pub fn draw<B, I>(&self, buf: &mut B, origin: DotPoint)
+    where
+        B: GetMut2D<Item = I>,
+        I: Blend,
+    {
+        blit_frame(buf, &self.tiles, origin, self.alpha, |tile, (tx, ty), _| {
+            tile.get_xy(tx, ty).and_then(|dot| *dot)
+        });
+    }
+
     fn get_debug_buf(&self) -> Option<Vec<Vec<Dot>>> {
         let (w, h) = (self.w_orig, self.h_orig);
         self.tiles.iter().try_fold(
@@ -507,7 +867,7 @@ impl Frame {
             },
         )
     }
-    fn restore_buf(&self) -> Option<RgbaImage> {
+    pub(crate) fn restore_buf(&self) -> Option<RgbaImage> {
         let color_buf = self.get_color_buf(&|d| match d {
             Some(rgb) => {
                 let mut res = rgb.to_rgba();
@@ -545,10 +905,37 @@ pub trait Collide {
     /// LeftUp Corner of Object
     fn origin(&self) -> DotPoint;
     fn mesh(&self) -> &MeshTree;
-    fn collide(&self, other: &impl Collide) {
+    fn collide(&self, other: &impl Collide) -> Option<Contact> {
+        self.collide_with(other, &LayerMatrix::default())
+    }
+    /// Detect collision between layers allowed by `matrix`
+    fn collide_with(&self, other: &impl Collide, matrix: &LayerMatrix) -> Option<Contact> {
         let origin_s = self.origin();
         let origin_o = other.origin();
-        self.mesh().collide(other.mesh(), origin_s, origin_o);
+        self.mesh().collide_with(other.mesh(), origin_s, origin_o, matrix)
+    }
+    /// Detect the first contact while self moves to `end_s` and other moves to `end_o`
+    fn sweep(&self, end_s: DotPoint, other: &impl Collide, end_o: DotPoint) -> Option<Sweep> {
+        self.sweep_with(end_s, other, end_o, &LayerMatrix::default())
+    }
+    fn sweep_with(
+        &self,
+        end_s: DotPoint,
+        other: &impl Collide,
+        end_o: DotPoint,
+        matrix: &LayerMatrix,
+    ) -> Option<Sweep> {
+        let path_s = self.origin()..end_s;
+        let path_o = other.origin()..end_o;
+        self.mesh().sweep(other.mesh(), path_s, path_o, matrix)
+    }
+    /// Cast a ray from `start` to `end` against the object
+    fn raycast(&self, start: DotPoint, end: DotPoint, mask: u8) -> Option<RayHit> {
+        self.mesh().raycast(self.origin(), start, end, mask)
+    }
+    /// Bounding box in world coordinates
+    fn world_bbox(&self) -> DotRect {
+        self.mesh().bbox().translate(&self.origin().to_vector())
     }
 }
 
@@ -579,21 +966,224 @@ mod frame_test {
         let bullet = load_mesh("../test-assets/bullet.png");
         let chara1 = load_mesh("../test-assets/chara1.png");
         let c = chara1.collide(&bullet, point2(0, 0), point2(0, 0));
-        assert_eq!(c, Some(rect(7, 8, 1, 1)))
+        assert_eq!(c.map(|c| c.rect), Some(rect(7, 8, 1, 1)));
+        assert_ne!(c.unwrap().bits, 0);
     }
     #[test]
     fn collide_l_2() {
         let bullet = load_mesh("../test-assets/bullet.png");
         let chara1 = load_mesh("../test-assets/chara1.png");
         let c = chara1.collide(&bullet, point2(16, 16), point2(12, 11));
-        assert_eq!(c, Some(rect(19, 19, 1, 1)));
+        assert_eq!(c.map(|c| c.rect), Some(rect(19, 19, 1, 1)));
     }
     #[test]
     fn collide_n_1() {
         let chara1 = load_mesh("../test-assets/chara1.png");
         let chara2 = load_mesh("../test-assets/chara2.png");
         let c = chara2.collide(&chara1, point2(0, 0), point2(19, 16));
-        assert_eq!(c, Some(rect(21, 18, 10, 14)));
+        assert_eq!(c.map(|c| c.rect), Some(rect(21, 18, 10, 14)));
+    }
+    #[test]
+    fn draw_to_img_buf() {
+        let chara2 = load_frame("../test-assets/chara2.png");
+        let dots = chara2.get_debug_buf().unwrap();
+        let white = Rgba { data: [255; 4] };
+        let mut img = RgbaImage::from_pixel(48, 48, white);
+        chara2.draw(&mut img, point2(5, 3));
+        let range = RectRange::zero_start(chara2.w_orig, chara2.h_orig).unwrap();
+        assert!(range.into_iter().all(|(x, y)| {
+            let mut expected = white;
+            if let Some(c) = dots[y][x] {
+                expected.blend(c, chara2.alpha);
+            }
+            *img.get_pixel(x as u32 + 5, y as u32 + 3) == expected
+        }));
+    }
+    #[test]
+    fn draw_clipped() {
+        let chara2 = load_frame("../test-assets/chara2.png");
+        let white = Rgba { data: [255; 4] };
+        let dots = chara2.get_debug_buf().unwrap();
+        for &(ox, oy) in &[(-8i32, -10i32), (10, 12)] {
+            let mut img = RgbaImage::from_pixel(16, 16, white);
+            chara2.draw(&mut img, point2(ox as i16, oy as i16));
+            let mut drawn = 0;
+            for (x, y, p) in img.enumerate_pixels() {
+                let (fx, fy) = (x as i32 - ox, y as i32 - oy);
+                let mut expected = white;
+                let inside = fx >= 0
+                    && fy >= 0
+                    && (fx as usize) < chara2.w_orig
+                    && (fy as usize) < chara2.h_orig;
+                if inside {
+                    if let Some(c) = dots[fy as usize][fx as usize] {
+                        expected.blend(c, chara2.alpha);
+                        drawn += 1;
+                    }
+                }
+                assert_eq!(*p, expected);
+            }
+            assert!(drawn > 0);
+        }
+    }
+    #[test]
+    fn draw_frame_far_origin() {
+        let white = Rgba { data: [255; 4] };
+        let black = Rgba { data: [0, 0, 0, 255] };
+        let frame = Frame::from_buf(&RgbaImage::from_pixel(64, 32, black), "wide").unwrap();
+        let mut img = RgbaImage::from_pixel(16, 16, white);
+        let max = i16::max_value();
+        frame.draw(&mut img, point2(max - 20, 0));
+        frame.draw(&mut img, point2(0, max - 4));
+        assert!(img.pixels().all(|p| *p == white));
+        frame.draw(&mut img, point2(-56, -30));
+        assert_eq!(img.pixels().filter(|p| **p != white).count(), 8 * 2);
+    }
+    #[test]
+    fn draw_tile_far_origin() {
+        let white = Rgba { data: [255; 4] };
+        let tile = Tile::new(Some(Color::black()));
+        let (min, max) = (i16::min_value(), i16::max_value());
+        let mut img = RgbaImage::from_pixel(16, 16, white);
+        for &(x, y) in &[(max - 4, max - 4), (max, 0), (min, min), (0, min + 8)] {
+            tile.draw(&mut img, x, y, Alpha(15));
+        }
+        assert!(img.pixels().all(|p| *p == white));
+        tile.draw(&mut img, -12, -12, Alpha(15));
+        assert_eq!(img.pixels().filter(|p| **p != white).count(), 16);
+    }
+    #[test]
+    fn collide_layers() {
+        use collision::CollisionLayer;
+        let body = CollisionLayer::PLAYER_BODY;
+        let bullet = CollisionLayer::ENEMY_BULLET;
+        let player = MeshTree::solid_tile(body.bit());
+        let enemy_bullet = MeshTree::solid_tile(bullet.bit());
+        let (p, q) = (point2(0, 0), point2(8, 8));
+        assert_eq!(player.collide(&enemy_bullet, p, q), None);
+        let mut matrix = LayerMatrix::default();
+        matrix.enable(body, bullet);
+        let c = player.collide_with(&enemy_bullet, p, q, &matrix).unwrap();
+        assert_eq!(c.rect, rect(8, 8, 8, 8));
+        assert_eq!(c.bits, body.bit());
+        assert!(c.pairs.contains(body, bullet));
+        let c = enemy_bullet.collide_with(&player, q, p, &matrix).unwrap();
+        assert_eq!(c.bits, bullet.bit());
+        assert!(c.pairs.contains(bullet, body));
+        assert!(!c.pairs.contains(body, bullet));
+    }
+    #[test]
+    fn collide_all_children() {
+        use collision::CollisionLayer;
+        let (body, wall) = (CollisionLayer::PLAYER_BODY, CollisionLayer::WALL);
+        let both = body.bit() | wall.bit();
+        // the left tile is on PLAYER_BODY, and the right tile is on WALL
+        let img = RgbaImage::from_fn(32, 16, |x, _| {
+            let bits = if x < 16 { body.bit() } else { wall.bit() };
+            Rgba {
+                data: [0, 0, 0, 0xf0 | bits],
+            }
+        });
+        let mesh = MeshTree::from_buf(&img).unwrap();
+        let (p, q) = (point2(0, 0), point2(8, 4));
+        // leaf against node
+        let tile = MeshTree::solid_tile(both);
+        for c in &[mesh.collide(&tile, p, q).unwrap(), tile.collide(&mesh, q, p).unwrap()] {
+            assert_eq!(c.rect, rect(8, 4, 16, 12));
+            assert_eq!(c.bits, both);
+            assert!(c.pairs.contains(body, body));
+            assert!(c.pairs.contains(wall, wall));
+        }
+        // node against node
+        let img = RgbaImage::from_pixel(24, 16, Rgba { data: [0, 0, 0, 0xf0 | both] });
+        let other = MeshTree::from_buf(&img).unwrap();
+        let c = mesh.collide(&other, p, q).unwrap();
+        assert_eq!(c.rect, rect(8, 4, 24, 12));
+        assert_eq!(c.bits, both);
+        assert!(c.pairs.contains(body, body));
+        assert!(c.pairs.contains(wall, wall));
+    }
+    fn dots_mesh(dots: &[(u32, u32)]) -> MeshTree {
+        let dot = Rgba {
+            data: [0, 0, 0, 0xf1],
+        };
+        let mut img = RgbaImage::new(16, 16);
+        dots.iter().for_each(|&(x, y)| img.put_pixel(x, y, dot));
+        MeshTree::from_buf(&img).unwrap()
+    }
+    #[test]
+    fn sweep_no_tunneling() {
+        let wall: Vec<_> = (0..16).map(|y| (8, y)).collect();
+        let wall = dots_mesh(&wall);
+        let bullet = dots_mesh(&[(0, 0)]);
+        let matrix = LayerMatrix::default();
+        let (o, start, end) = (point2(0, 0), point2(0, 4), point2(15, 4));
+        assert_eq!(wall.collide(&bullet, o, start), None);
+        assert_eq!(wall.collide(&bullet, o, end), None);
+        let s = wall.sweep(&bullet, o..o, start..end, &matrix).unwrap();
+        assert_eq!(s.time, 8.0 / 15.0);
+        assert_eq!((s.pos_s, s.pos_o), (o, point2(8, 4)));
+        assert_eq!(s.contact.rect, rect(8, 4, 1, 1));
+        let miss = wall.sweep(&bullet, o..o, point2(0, 20)..point2(15, 20), &matrix);
+        assert_eq!(miss, None);
+    }
+    #[test]
+    fn sweep_both_moving() {
+        let wall: Vec<_> = (0..16).map(|y| (8, y)).collect();
+        let wall = dots_mesh(&wall);
+        let bullet = dots_mesh(&[(0, 0)]);
+        let path_s = point2(0, 0)..point2(10, 0);
+        let path_o = point2(20, 0)..point2(-10, 0);
+        let s = wall.sweep(&bullet, path_s, path_o, &LayerMatrix::default());
+        let s = s.unwrap();
+        assert_eq!(s.time, 12.0 / 40.0);
+        assert_eq!((s.pos_s, s.pos_o), (point2(3, 0), point2(11, 0)));
+        assert_eq!(s.contact.rect, rect(11, 0, 1, 1));
+    }
+    #[test]
+    fn raycast_wall() {
+        let wall: Vec<_> = (0..16).map(|y| (8, y)).collect();
+        let wall = dots_mesh(&wall);
+        let o = point2(10, 0);
+        let hit = wall.raycast(o, point2(0, 4), point2(40, 4), 0xf).unwrap();
+        assert_eq!((hit.point, hit.distance, hit.bits), (point2(18, 4), 18.0, 1));
+        let hit = wall.raycast(o, point2(40, 4), point2(0, 4), 0xf).unwrap();
+        assert_eq!((hit.point, hit.distance), (point2(18, 4), 22.0));
+        let hit = wall.raycast(o, point2(0, 0), point2(30, 15), 0xf).unwrap();
+        assert_eq!((hit.point, hit.distance), (point2(18, 9), 405f32.sqrt()));
+        assert_eq!(wall.raycast(o, point2(0, 4), point2(17, 4), 0xf), None);
+        assert_eq!(wall.raycast(o, point2(0, 4), point2(40, 4), 0b10), None);
+        assert_eq!(wall.raycast(o, point2(0, 20), point2(40, 20), 0xf), None);
+    }
+    #[test]
+    fn large_mesh() {
+        let dot = Rgba {
+            data: [0, 0, 0, 0xf1],
+        };
+        let mut img = RgbaImage::new(4200, 40);
+        img.put_pixel(5, 5, dot);
+        img.put_pixel(4190, 30, dot);
+        let frame = Frame::from_buf(&img, "large").unwrap();
+        assert_eq!(frame.bbox(), rect(5, 5, 4186, 26));
+        let mut small = RgbaImage::new(16, 16);
+        small.put_pixel(0, 0, dot);
+        let small = MeshTree::from_buf(&small).unwrap();
+        let (o, p) = (point2(0, 0), point2(4190, 30));
+        let c = frame.mesh.collide(&small, o, p);
+        assert_eq!(c.map(|c| c.rect), Some(rect(4190, 30, 1, 1)));
+        let c = small.collide(&frame.mesh, p, o);
+        assert_eq!(c.map(|c| c.rect), Some(rect(4190, 30, 1, 1)));
+        assert_eq!(frame.mesh.collide(&small, o, point2(4189, 30)), None);
+        let c = frame.mesh.collide(&frame.mesh, o, point2(4185, 25));
+        assert_eq!(c.map(|c| c.rect), Some(rect(4190, 30, 1, 1)));
+    }
+    #[test]
+    fn too_large() {
+        let img = RgbaImage::new(40000, 1);
+        let err = Frame::from_buf(&img, "").err();
+        assert_eq!(err, Some(FrameError::TooLarge(40000, 1)));
+        let img = RgbaImage::new(16, 16);
+        assert_eq!(MeshTree::from_buf(&img).err(), Some(FrameError::NoMesh));
     }
     #[test]
     fn frame_to_img_buf() {
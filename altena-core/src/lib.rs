@@ -1,5 +1,7 @@
 #![feature(dyn_trait, iterator_try_fold, nll, test, try_trait)]
 extern crate ansi_term;
+extern crate base64;
+extern crate byteorder;
 extern crate euclid;
 extern crate graphics;
 extern crate image;
@@ -11,19 +13,35 @@ extern crate piston;
 extern crate rect_iter;
 extern crate rusttype;
 extern crate sdl2_window;
+extern crate serde_json;
 extern crate test;
 extern crate tuple_map;
+extern crate xml;
 
 mod app;
+mod atlas;
+mod binary;
+mod bitmap_font;
+mod camera;
+mod collision;
 mod font;
+mod font_stack;
 mod frame;
 mod input;
+mod markup;
+mod overlay;
+mod palette;
+mod palette_effect;
+mod primitive;
 mod scene;
 mod schedule;
 mod simulator;
 #[cfg(test)]
 mod testutils;
+mod text;
 mod tile;
+mod tiled;
+mod tilemap;
 mod ui;
 
 use opengl_graphics::{Filter, GlGraphics, OpenGL, Texture, TextureSettings};
@@ -37,9 +55,13 @@ use std::rc::Rc;
 use tuple_map::*;
 
 use app::{App, AppMessage};
+use camera::Camera;
 use frame::dottypes::*;
 use input::InputHandler;
 
+pub use atlas::GlyphAtlas;
+pub use font::{FontHandle, FontSetting, TextDraw};
+
 /// clock counter type
 /// currently we use Update event as a counter, but it may be changed in the future
 pub type Clock = u64;
@@ -87,6 +109,11 @@ impl AltenaCore {
 
     fn register_app(app: impl App) {}
 
+    /// Convert mouse coordinates in the window into world coordinates seen by `camera`.
+    pub fn mouse_to_world(&self, camera: &Camera, mouse_xy: (f64, f64)) -> DotPoint {
+        camera.real_to_world(mouse_xy, (self.x_scale, self.y_scale))
+    }
+
     fn from_setting(setting: AltenaSetting) -> AltenaCore {
         let texture_setting = TextureSettings::new().filter(Filter::Nearest);
         let texture = Texture::empty(&texture_setting).expect("couldn't make OpenGL texture");
@@ -0,0 +1,498 @@
+//! rich text markup for dialogue and the typewriter effect
+//!
+//! Tags:
+//! - `[color=ff0000]text[/color]` changes the color(nestable)
+//! - `[wait=30]` pauses the typewriter for 30 ticks
+//! - `[speed=2]` reveals 2 characters per tick from here
+//! - `[ruby=かんじ]漢字[/ruby]` draws ruby(furigana) over the text
+//! - `[[` is a literal `[`
+
+use rect_iter::GetMut2D;
+
+use euclid::vec2;
+
+use std::cmp;
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+
+use font::{self, FontError, FontSetting, TextFont};
+use frame::dottypes::*;
+use text::{self, Align, FontMetrics, PlacedGlyph, TextLayout};
+use tile::{Blend, Color};
+use Clock;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MarkupError {
+    /// tag or ruby/color isn't closed
+    Unclosed(String),
+    /// closing tag without opening tag
+    Unmatched(String),
+    Unknown(String),
+    Value(String),
+}
+
+impl Error for MarkupError {
+    fn description(&self) -> &str {
+        "Markup Error"
+    }
+}
+
+impl fmt::Display for MarkupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            MarkupError::Unclosed(s) => write!(f, "Unclosed: {}", s),
+            MarkupError::Unmatched(s) => write!(f, "Unmatched: {}", s),
+            MarkupError::Unknown(s) => write!(f, "Unknown tag: {}", s),
+            MarkupError::Value(s) => write!(f, "Value: {}", s),
+        }
+    }
+}
+
+/// Text drawn with the same color
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TextSpan {
+    pub text: String,
+    /// None means FontSetting's color
+    pub color: Option<Color>,
+}
+
+/// Ruby(furigana) over a range of characters
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ruby {
+    /// base characters(in chars of the plain text)
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+/// Typewriter control placed before a character
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Control {
+    Wait(Clock),
+    Speed(u32),
+}
+
+/// Parsed markup
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RichText {
+    spans: Vec<TextSpan>,
+    rubies: Vec<Ruby>,
+    /// (index of the character, control), sorted by index
+    controls: Vec<(usize, Control)>,
+    plain: String,
+}
+
+impl RichText {
+    pub fn parse(s: &str) -> Result<RichText, MarkupError> {
+        let mut res = RichText::default();
+        let mut colors: Vec<Color> = Vec::new();
+        let mut ruby: Option<(usize, String)> = None;
+        let mut current = String::new();
+        let mut n = 0;
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '[' || chars.peek() == Some(&'[') {
+                if c == '[' {
+                    chars.next();
+                }
+                current.push(c);
+                n += 1;
+                continue;
+            }
+            let (mut tag, mut closed) = (String::new(), false);
+            for c in chars.by_ref() {
+                if c == ']' {
+                    closed = true;
+                    break;
+                }
+                tag.push(c);
+            }
+            if !closed {
+                return Err(MarkupError::Unclosed(tag));
+            }
+            res.push_span(&mut current, colors.last().cloned());
+            let (name, value) = match tag.find('=') {
+                Some(i) => (&tag[..i], &tag[i + 1..]),
+                None => (&tag[..], ""),
+            };
+            let value_err = || MarkupError::Value(tag.clone());
+            match name {
+                "color" => colors.push(parse_color(value).ok_or_else(value_err)?),
+                "/color" => {
+                    colors.pop().ok_or_else(|| MarkupError::Unmatched(tag.clone()))?;
+                }
+                "wait" => {
+                    let w = value.parse().map_err(|_| value_err())?;
+                    res.controls.push((n, Control::Wait(w)));
+                }
+                "speed" => match value.parse() {
+                    Ok(sp) if sp > 0 => res.controls.push((n, Control::Speed(sp))),
+                    _ => return Err(value_err()),
+                },
+                "ruby" => {
+                    if ruby.is_some() || value.is_empty() {
+                        return Err(value_err());
+                    }
+                    ruby = Some((n, value.to_owned()));
+                }
+                "/ruby" => {
+                    let (start, text) = ruby.take()
+                        .ok_or_else(|| MarkupError::Unmatched(tag.clone()))?;
+                    res.rubies.push(Ruby {
+                        range: start..n,
+                        text: text,
+                    });
+                }
+                _ => return Err(MarkupError::Unknown(tag.clone())),
+            }
+        }
+        res.push_span(&mut current, colors.last().cloned());
+        if let Some((_, text)) = ruby {
+            return Err(MarkupError::Unclosed(format!("ruby={}", text)));
+        }
+        if !colors.is_empty() {
+            return Err(MarkupError::Unclosed("color".to_owned()));
+        }
+        Ok(res)
+    }
+    fn push_span(&mut self, text: &mut String, color: Option<Color>) {
+        if text.is_empty() {
+            return;
+        }
+        self.plain.push_str(text);
+        self.spans.push(TextSpan {
+            text: text.split_off(0),
+            color: color,
+        });
+    }
+    pub fn spans(&self) -> &[TextSpan] {
+        &self.spans
+    }
+    pub fn rubies(&self) -> &[Ruby] {
+        &self.rubies
+    }
+    pub fn controls(&self) -> &[(usize, Control)] {
+        &self.controls
+    }
+    /// Text without tags
+    pub fn plain(&self) -> &str {
+        &self.plain
+    }
+    /// Number of characters without tags
+    pub fn len(&self) -> usize {
+        self.plain.chars().count()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.plain.is_empty()
+    }
+    /// Color of the character at `index`
+    pub fn color_at(&self, index: usize) -> Option<Color> {
+        let mut start = 0;
+        for span in &self.spans {
+            start += span.text.chars().count();
+            if index < start {
+                return span.color;
+            }
+        }
+        None
+    }
+}
+
+/// Parse `rrggbb` or `#rrggbb`
+fn parse_color(s: &str) -> Option<Color> {
+    let hex = s.trim_left_matches('#');
+    if hex.len() != 6 {
+        return None;
+    }
+    let v = u32::from_str_radix(hex, 16).ok()?;
+    Some(Color {
+        r: (v >> 16) as u8,
+        g: (v >> 8) as u8,
+        b: v as u8,
+    })
+}
+
+/// Layout of rich text, shared by drawing and measurement
+pub(crate) struct RichLayout {
+    /// layout of the text without ruby
+    pub(crate) plain: TextLayout,
+    /// (glyph, scale, color) of the visible text and ruby
+    pub(crate) glyphs: Vec<(PlacedGlyph, u8, Color)>,
+    /// bounding rect of the whole text and ruby, including effects
+    pub(crate) bbox: DotRect,
+}
+
+/// Lay out `rich` as the whole text so that revealing characters never moves
+/// them, but only the first `visible` characters and their ruby go to `glyphs`.
+pub(crate) fn layout_rich<F>(
+    font: &F,
+    rich: &RichText,
+    setting: &FontSetting,
+    visible: usize,
+) -> Result<RichLayout, FontError>
+where
+    F: FontMetrics + ?Sized,
+{
+    let layout = text::layout(font, rich.plain(), setting)?;
+    let scale = setting.scale_u;
+    // (glyph, scale, color) of the text and ruby, drawn at once so that
+    // strict mode checks all of them before drawing
+    let mut glyphs: Vec<_> = layout
+        .glyphs
+        .iter()
+        .filter(|g| g.index < visible)
+        .map(|&g| (g, scale, rich.color_at(g.index).unwrap_or(setting.color)))
+        .collect();
+    // ruby is drawn at half of the scale, over the line(or right of the column
+    // in vertical text)
+    let metrics = font.line_metrics(f32::from(scale));
+    let (ascent, em) = (metrics.ascent, metrics.ascent - metrics.descent);
+    let ruby_scale = cmp::max(1, scale / 2);
+    let mut ruby_setting = setting.clone();
+    ruby_setting.scale(ruby_scale).start((0.0, 0.0)).align(Align::Left).max_width = None;
+    ruby_setting.max_height = None;
+    let mut bbox = layout.bbox;
+    for ruby in rich.rubies() {
+        let base: Vec<_> = layout
+            .glyphs
+            .iter()
+            .filter(|g| ruby.range.start <= g.index && g.index < ruby.range.end)
+            .collect();
+        let first = match base.first() {
+            Some(g) => g,
+            None => continue,
+        };
+        let last = base.iter().filter(|g| g.line == first.line).last().unwrap_or(first);
+        let ruby_layout = text::layout(font, &ruby.text, &ruby_setting)?;
+        let size = ruby_layout.bbox.size;
+        let (w, h) = (f32::from(size.width), f32::from(size.height));
+        let (x, y) = if setting.vertical {
+            let center = (first.y + last.y + em) / 2.0 - ascent;
+            (first.x + em, center - h / 2.0)
+        } else {
+            let last_adv = font.advance(last.c, f32::from(scale)).unwrap_or(0.0);
+            let center = (first.x + last.x + last_adv) / 2.0;
+            (center - w / 2.0, first.y - ascent - h)
+        };
+        let (x, y) = (x.round(), y.round());
+        bbox = bbox.union(&ruby_layout.bbox.translate(&vec2(x as i16, y as i16)));
+        if ruby.range.start >= visible {
+            continue;
+        }
+        let color = rich.color_at(first.index).unwrap_or(setting.color);
+        for g in &ruby_layout.glyphs {
+            let placed = PlacedGlyph {
+                x: g.x + x,
+                y: g.y + y,
+                ..*g
+            };
+            glyphs.push((placed, ruby_scale, color));
+        }
+    }
+    Ok(RichLayout {
+        plain: layout,
+        glyphs: glyphs,
+        bbox: setting.effects.ink_rect(bbox),
+    })
+}
+
+/// Draw the first `visible` characters of `text`, laid out as the whole text
+/// so that revealing characters never moves them.
+pub(crate) fn draw_rich<F, B, I>(
+    font: &mut F,
+    buf: &mut B,
+    rich: &RichText,
+    setting: &FontSetting,
+    visible: usize,
+) -> Result<DotRect, FontError>
+where
+    F: TextFont + ?Sized,
+    B: GetMut2D<Item = I>,
+    I: Blend,
+{
+    let layout = layout_rich(font, rich, setting, visible)?;
+    font::draw_glyphs(font, buf, &layout.glyphs, setting)?;
+    Ok(layout.bbox)
+}
+
+/// Reveals RichText character by character, driven by the update Clock
+#[derive(Clone, Debug)]
+pub struct Typewriter {
+    text: RichText,
+    /// characters per tick
+    speed: u32,
+    revealed: usize,
+    /// next control to apply
+    next_control: usize,
+    /// characters aren't revealed before this
+    resume: Clock,
+    now: Clock,
+}
+
+impl Typewriter {
+    /// Start revealing `text` at `start`, `speed` characters per tick.
+    /// Speed 0 is treated as 1.
+    pub fn new(text: RichText, speed: u32, start: Clock) -> Typewriter {
+        Typewriter {
+            text: text,
+            speed: cmp::max(speed, 1),
+            revealed: 0,
+            next_control: 0,
+            resume: start,
+            now: start,
+        }
+    }
+    /// Reveal characters until `now`
+    pub fn update(&mut self, now: Clock) {
+        while self.now < now && !self.is_finished() {
+            self.now += 1;
+            self.tick();
+        }
+        self.now = cmp::max(self.now, now);
+    }
+    fn tick(&mut self) {
+        let mut budget = self.speed;
+        while self.now >= self.resume && budget > 0 && !self.is_finished() {
+            while let Some(&(i, control)) = self.text.controls.get(self.next_control) {
+                if i != self.revealed {
+                    break;
+                }
+                self.next_control += 1;
+                match control {
+                    Control::Wait(w) => {
+                        self.resume = self.now + w;
+                        return;
+                    }
+                    Control::Speed(s) => {
+                        self.speed = s;
+                        budget = s;
+                    }
+                }
+            }
+            self.revealed += 1;
+            budget -= 1;
+        }
+    }
+    /// Reveal all characters(e.g. when the player presses a button)
+    pub fn skip(&mut self) {
+        self.revealed = self.text.len();
+        self.next_control = self.text.controls.len();
+    }
+    pub fn is_finished(&self) -> bool {
+        self.revealed >= self.text.len()
+    }
+    /// Number of revealed characters
+    pub fn revealed(&self) -> usize {
+        self.revealed
+    }
+    pub fn text(&self) -> &RichText {
+        &self.text
+    }
+    /// Draw revealed characters. The layout is the same as the whole text.
+    pub fn draw<F, B, I>(
+        &self,
+        font: &mut F,
+        buf: &mut B,
+        setting: &FontSetting,
+    ) -> Result<DotRect, FontError>
+    where
+        F: TextFont + ?Sized,
+        B: GetMut2D<Item = I>,
+        I: Blend,
+    {
+        draw_rich(font, buf, &self.text, setting, self.revealed)
+    }
+}
+
+#[cfg(test)]
+mod markup_test {
+    use super::*;
+    use bitmap_font::BitmapFont;
+    use font::TextDraw;
+    use text::TextMeasure;
+    use image::{Rgba, RgbaImage};
+    use std::path::Path;
+    #[test]
+    fn parse() {
+        let s = "a[color=#ff0000]b[[[wait=3]c[/color][ruby=かんじ]漢字[/ruby]";
+        let text = RichText::parse(s).unwrap();
+        assert_eq!(text.plain(), "ab[c漢字");
+        assert_eq!(text.spans().len(), 4);
+        let red = Color { r: 255, g: 0, b: 0 };
+        assert_eq!(text.color_at(0), None);
+        assert_eq!(text.color_at(1), Some(red));
+        assert_eq!(text.color_at(3), Some(red));
+        assert_eq!(text.color_at(4), None);
+        assert_eq!(text.controls(), &[(3, Control::Wait(3))]);
+        assert_eq!(text.rubies()[0].range, 4..6);
+    }
+    #[test]
+    fn parse_error() {
+        let err = |s| RichText::parse(s).unwrap_err();
+        assert_eq!(err("[color=red]a[/color]"), MarkupError::Value("color=red".to_owned()));
+        assert_eq!(err("a[/color]"), MarkupError::Unmatched("/color".to_owned()));
+        assert_eq!(err("[color=ffffff]a"), MarkupError::Unclosed("color".to_owned()));
+        assert_eq!(err("[bold]a"), MarkupError::Unknown("bold".to_owned()));
+        assert_eq!(err("a[wait=3"), MarkupError::Unclosed("wait=3".to_owned()));
+    }
+    #[test]
+    fn typewriter() {
+        let text = RichText::parse("abc[wait=3]de[speed=3]fghij").unwrap();
+        let mut tw = Typewriter::new(text, 1, 10);
+        tw.update(10);
+        assert_eq!(tw.revealed(), 0);
+        tw.update(13);
+        assert_eq!(tw.revealed(), 3);
+        // waiting
+        tw.update(16);
+        assert_eq!(tw.revealed(), 3);
+        tw.update(18);
+        assert_eq!(tw.revealed(), 5);
+        tw.update(19);
+        assert_eq!(tw.revealed(), 8);
+        tw.update(100);
+        assert!(tw.is_finished());
+        let mut tw = Typewriter::new(RichText::parse("abc").unwrap(), 1, 0);
+        tw.skip();
+        assert!(tw.is_finished());
+    }
+    #[test]
+    fn typewriter_zero_speed() {
+        let mut tw = Typewriter::new(RichText::parse("abc").unwrap(), 0, 0);
+        tw.update(2);
+        assert_eq!(tw.revealed(), 2);
+        tw.update(100);
+        assert!(tw.is_finished());
+    }
+    #[test]
+    fn draw_colors() {
+        let white = Rgba { data: [255; 4] };
+        let mut font = BitmapFont::load(Path::new("../test-assets/font/tiny.bdf")).unwrap();
+        let mut setting = FontSetting::new();
+        setting.scale(8).start((0.0, 8.0));
+        let text = RichText::parse("A[color=ff0000]A[/color][ruby=AB]B[/ruby]").unwrap();
+        let mut img = RgbaImage::from_pixel(32, 24, white);
+        let bbox = font.draw_rich(&mut img, &text, &setting).unwrap();
+        assert_eq!(bbox.size.width, 18);
+        // the bbox includes ruby
+        assert!(bbox.origin.y < 8);
+        assert_eq!(font.measure_rich(&text, &setting).unwrap().bbox, bbox);
+        // top of 'A's
+        assert_eq!(img.get_pixel(2, 8).data, [0, 0, 0, 255]);
+        assert_eq!(img.get_pixel(8, 8).data, [255, 0, 0, 255]);
+        // ruby is over the line
+        assert!((0..8).any(|y| (0..32).any(|x| img.get_pixel(x, y) != &white)));
+        // typewriter draws with the same layout
+        let mut tw = Typewriter::new(text, 1, 0);
+        tw.update(2);
+        let mut partial = RgbaImage::from_pixel(32, 24, white);
+        tw.draw(&mut font, &mut partial, &setting).unwrap();
+        for (x, y, p) in partial.enumerate_pixels() {
+            if x < 12 && y >= 8 {
+                assert_eq!(p, img.get_pixel(x, y));
+            } else {
+                assert_eq!(p, &white);
+            }
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! debug overlay which shows collision meshes on the screen
+
+use rect_iter::GetMut2D;
+
+use camera::Camera;
+use collision::{CollisionLayer, LAYER_NUM};
+use frame::dottypes::*;
+use frame::{Collide, Contact, MeshTree};
+use primitive;
+use tile::{Alpha, Blend, Color};
+
+/// Renderer for collision meshes, their quadtree bboxes and contacts.
+/// It does nothing while disabled, so it can be toggled at runtime.
+#[derive(Clone, Debug)]
+pub struct MeshOverlay {
+    enabled: bool,
+    /// colors of mesh dots for each collision layer
+    layer_colors: [Color; LAYER_NUM],
+    bbox_color: Color,
+    contact_color: Color,
+    show_bboxes: bool,
+    alpha: Alpha,
+}
+
+impl Default for MeshOverlay {
+    fn default() -> MeshOverlay {
+        MeshOverlay::new()
+    }
+}
+
+impl MeshOverlay {
+    pub fn new() -> MeshOverlay {
+        let color = |r, g, b| Color { r: r, g: g, b: b };
+        MeshOverlay {
+            enabled: false,
+            layer_colors: [
+                color(0, 255, 0),
+                color(255, 255, 0),
+                color(255, 0, 0),
+                color(0, 0, 255),
+            ],
+            bbox_color: color(0, 255, 255),
+            contact_color: color(255, 0, 255),
+            show_bboxes: true,
+            alpha: Alpha(8),
+        }
+    }
+    pub fn layer_color(&mut self, layer: CollisionLayer, color: Color) -> &mut MeshOverlay {
+        self.layer_colors[layer.index()] = color;
+        self
+    }
+    pub fn bbox_color(&mut self, color: Color) -> &mut MeshOverlay {
+        self.bbox_color = color;
+        self
+    }
+    pub fn contact_color(&mut self, color: Color) -> &mut MeshOverlay {
+        self.contact_color = color;
+        self
+    }
+    pub fn show_bboxes(&mut self, b: bool) -> &mut MeshOverlay {
+        self.show_bboxes = b;
+        self
+    }
+    /// Alpha of mesh dots(bboxes and contacts are drawn opaque)
+    pub fn alpha(&mut self, alpha: Alpha) -> &mut MeshOverlay {
+        self.alpha = alpha;
+        self
+    }
+    pub fn enabled(&mut self, b: bool) -> &mut MeshOverlay {
+        self.enabled = b;
+        self
+    }
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+    /// Switch the overlay on/off, e.g. when a debug key is pressed
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+    }
+    /// Draw `mesh` with its upper left corner at `origin`(screen coordinates)
+    pub fn draw_mesh<B, I>(&self, buf: &mut B, mesh: &MeshTree, origin: DotPoint)
+    where
+        B: GetMut2D<Item = I>,
+        I: Blend,
+    {
+        if !self.enabled {
+            return;
+        }
+        mesh.for_each_dot(origin, &mut |p, bits| {
+            if let Some(b) = buf.get_mut_xy(p.x, p.y) {
+                CollisionLayer::variants()
+                    .filter(|l| bits & l.bit() != 0)
+                    .for_each(|l| b.blend(self.layer_colors[l.index()], self.alpha));
+            }
+        });
+        if self.show_bboxes {
+            mesh.for_each_bbox(origin, &mut |r| {
+                primitive::rect(buf, r, self.bbox_color, Alpha(15))
+            });
+        }
+    }
+    /// Draw the mesh of `obj` seen by `camera`
+    pub fn draw_object<B, I>(&self, buf: &mut B, obj: &impl Collide, camera: &Camera)
+    where
+        B: GetMut2D<Item = I>,
+        I: Blend,
+    {
+        let origin = camera.world_to_screen(obj.origin());
+        self.draw_mesh(buf, obj.mesh(), origin);
+    }
+    /// Draw the rectangle of `contact` seen by `camera`
+    pub fn draw_contact<B, I>(&self, buf: &mut B, contact: &Contact, camera: &Camera)
+    where
+        B: GetMut2D<Item = I>,
+        I: Blend,
+    {
+        if !self.enabled {
+            return;
+        }
+        let origin = camera.world_to_screen(contact.rect.origin);
+        let rect = DotRect::new(origin, contact.rect.size);
+        primitive::rect(buf, rect, self.contact_color, Alpha(15));
+    }
+}
+
+#[cfg(test)]
+mod overlay_test {
+    use super::*;
+    use euclid::{point2, rect};
+    use image::{Rgba, RgbaImage};
+    use rect_iter::Get2D;
+    #[test]
+    fn draw_solid_tile() {
+        let black = Rgba { data: [0, 0, 0, 255] };
+        let mut img = RgbaImage::from_pixel(32, 32, black);
+        let mesh = MeshTree::solid_tile(CollisionLayer::PLAYER_BODY.bit());
+        let mut overlay = MeshOverlay::new();
+        overlay.draw_mesh(&mut img, &mesh, point2(4, 4));
+        assert!(img.pixels().all(|p| *p == black));
+        overlay.toggle();
+        overlay.alpha(Alpha(15));
+        overlay.draw_mesh(&mut img, &mesh, point2(4, 4));
+        let cyan = Color { r: 0, g: 255, b: 255 };
+        let green = Color { r: 0, g: 255, b: 0 };
+        let color = |x, y| Color::from_rgba(img.get_xy(x, y).unwrap()).unwrap();
+        assert_eq!(color(4, 4), cyan);
+        assert_eq!(color(19, 10), cyan);
+        assert_eq!(color(10, 10), green);
+        assert_eq!(color(20, 10), Color::black());
+    }
+    #[test]
+    fn draw_contact_with_camera() {
+        let black = Rgba { data: [0, 0, 0, 255] };
+        let mut img = RgbaImage::from_pixel(32, 32, black);
+        let mesh = MeshTree::solid_tile(1);
+        let contact = mesh.collide(&mesh, point2(100, 100), point2(108, 108)).unwrap();
+        assert_eq!(contact.rect, rect(108, 108, 8, 8));
+        let mut camera = Camera::new();
+        camera.move_to(point2(100, 100));
+        let mut overlay = MeshOverlay::new();
+        overlay.enabled(true).contact_color(Color::white());
+        overlay.draw_contact(&mut img, &contact, &camera);
+        let color = |x, y| Color::from_rgba(img.get_xy(x, y).unwrap()).unwrap();
+        assert_eq!(color(8, 8), Color::white());
+        assert_eq!(color(15, 12), Color::white());
+        assert_eq!(color(12, 12), Color::black());
+    }
+}
@@ -0,0 +1,424 @@
+//! palette and palette-indexed tiles
+
+use image::{self, ImageError, RgbaImage};
+use num_traits::ToPrimitive;
+use rect_iter::{Get2D, GetMut2D};
+
+use std::cmp::min;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use frame::dottypes::*;
+use frame::{blit_frame, Frame, FrameError, MeshTree};
+use palette_effect::ScanlinePalettes;
+use tile::tiletypes::*;
+use tile::{blit_tile, Alpha, Blend, Color, Tile};
+
+/// Palette can have up to 256 colors, so that indices fit in u8
+pub const MAX_COLORS: usize = 256;
+
+#[derive(Debug)]
+pub enum PaletteError {
+    Io(io::Error),
+    Image(ImageError),
+    Format(String),
+    /// palette has more colors than MAX_COLORS
+    TooMany(usize),
+    Empty,
+}
+
+impl Error for PaletteError {
+    fn description(&self) -> &str {
+        "Palette Error"
+    }
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            PaletteError::Io(e) => write!(f, "Io: {}", e),
+            PaletteError::Image(e) => write!(f, "Image: {}", e),
+            PaletteError::Format(s) => write!(f, "Format: {}", s),
+            PaletteError::TooMany(n) => write!(f, "TooMany: {} colors", n),
+            PaletteError::Empty => write!(f, "Empty"),
+        }
+    }
+}
+
+impl From<io::Error> for PaletteError {
+    fn from(e: io::Error) -> PaletteError {
+        PaletteError::Io(e)
+    }
+}
+
+impl From<ImageError> for PaletteError {
+    fn from(e: ImageError) -> PaletteError {
+        PaletteError::Image(e)
+    }
+}
+
+/// List of colors shared by indexed tiles.
+/// Colors are looked up when drawing, so changing the palette(or drawing with
+/// another palette) recolors everything drawn with it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Palette {
+    colors: Vec<Color>,
+}
+
+impl Palette {
+    pub fn new(colors: Vec<Color>) -> Result<Palette, PaletteError> {
+        if colors.is_empty() {
+            return Err(PaletteError::Empty);
+        }
+        if colors.len() > MAX_COLORS {
+            return Err(PaletteError::TooMany(colors.len()));
+        }
+        Ok(Palette { colors: colors })
+    }
+    /// Load palette from .gpl, .hex or image file
+    pub fn load(path: &Path) -> Result<Palette, PaletteError> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("gpl") => Palette::from_gpl(&fs::read_to_string(path)?),
+            Some("hex") => Palette::from_hex(&fs::read_to_string(path)?),
+            _ => Palette::from_buf(&image::open(path)?.to_rgba()),
+        }
+    }
+    /// Distinct opaque colors in the image, in row-major order
+    pub fn from_buf(buf: &RgbaImage) -> Result<Palette, PaletteError> {
+        let mut colors = Vec::new();
+        for c in buf.pixels().filter_map(Color::from_rgba) {
+            if !colors.contains(&c) {
+                colors.push(c);
+            }
+            if colors.len() > MAX_COLORS {
+                return Err(PaletteError::TooMany(colors.len()));
+            }
+        }
+        Palette::new(colors)
+    }
+    /// Parse GIMP palette
+    pub fn from_gpl(s: &str) -> Result<Palette, PaletteError> {
+        let mut lines = s.lines();
+        match lines.next() {
+            Some(l) if l.trim() == "GIMP Palette" => {}
+            _ => return Err(PaletteError::Format("no GIMP Palette header".to_owned())),
+        }
+        let is_header = |l: &str| l.starts_with("Name:") || l.starts_with("Columns:");
+        let colors = lines
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#') && !is_header(l))
+            .map(|l| {
+                let mut values = l.split_whitespace().map(|v| v.parse::<u8>());
+                let mut next = || match values.next() {
+                    Some(Ok(v)) => Ok(v),
+                    _ => Err(PaletteError::Format(format!("wrong line: {}", l))),
+                };
+                Ok(Color {
+                    r: next()?,
+                    g: next()?,
+                    b: next()?,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Palette::new(colors)
+    }
+    /// Parse hex palette(one RRGGBB per line)
+    pub fn from_hex(s: &str) -> Result<Palette, PaletteError> {
+        let colors = s.lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .map(|l| {
+                let hex = l.trim_left_matches('#');
+                let v = match u32::from_str_radix(hex, 16) {
+                    Ok(v) if hex.len() == 6 => v,
+                    _ => return Err(PaletteError::Format(format!("wrong line: {}", l))),
+                };
+                Ok(Color {
+                    r: (v >> 16) as u8,
+                    g: (v >> 8) as u8,
+                    b: v as u8,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Palette::new(colors)
+    }
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+    pub fn colors(&self) -> &[Color] {
+        &self.colors
+    }
+    pub fn get(&self, index: u8) -> Option<Color> {
+        self.colors.get(usize::from(index)).cloned()
+    }
+    /// Replace the color of `index`. Returns false if `index` is out of the palette.
+    pub fn set(&mut self, index: u8, color: Color) -> bool {
+        match self.colors.get_mut(usize::from(index)) {
+            Some(c) => {
+                *c = color;
+                true
+            }
+            None => false,
+        }
+    }
+    /// Rotate colors from `first` to `last`(inclusive) by one entry,
+    /// so that the color of `first` moves to `first + 1`.
+    pub fn rotate(&mut self, first: u8, last: u8) {
+        let last = min(usize::from(last), self.colors.len() - 1);
+        let first = usize::from(first);
+        if first < last {
+            self.colors[first..=last].rotate_right(1);
+        }
+    }
+    /// Replace all colors with `color`, e.g. for damage flashes
+    pub fn fill(&mut self, color: Color) {
+        self.colors.iter_mut().for_each(|c| *c = color);
+    }
+    /// Index of the nearest color(by squared RGB distance)
+    pub fn nearest(&self, color: Color) -> u8 {
+        let dist = |c: &Color| {
+            let d = |a: u8, b: u8| {
+                let d = i32::from(a) - i32::from(b);
+                d * d
+            };
+            d(c.r, color.r) + d(c.g, color.g) + d(c.b, color.b)
+        };
+        self.colors
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, c)| dist(c))
+            .map_or(0, |(i, _)| i as u8)
+    }
+}
+
+/// 16×16 tile which stores palette indices instead of colors
+#[derive(Clone)]
+pub struct IndexedTile {
+    inner: [Option<u8>; TILE_SIZE * TILE_SIZE],
+}
+
+impl fmt::Debug for IndexedTile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "indexed tile: {{")?;
+        for row in self.inner.chunks(TILE_SIZE) {
+            for dot in row {
+                match dot {
+                    Some(i) => write!(f, "{:02x}", i)?,
+                    None => write!(f, "  ")?,
+                }
+            }
+            writeln!(f, "")?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl Default for IndexedTile {
+    fn default() -> IndexedTile {
+        IndexedTile {
+            inner: [None; TILE_SIZE * TILE_SIZE],
+        }
+    }
+}
+
+impl IndexedTile {
+    /// Quantize `tile` into the nearest colors of `palette`
+    pub fn from_tile(tile: &Tile, palette: &Palette) -> IndexedTile {
+        let mut res = IndexedTile::default();
+        for (x, y) in tile_rect() {
+            if let Some(Some(c)) = tile.get_xy(x, y) {
+                res.inner[y * TILE_SIZE + x] = Some(palette.nearest(*c));
+            }
+        }
+        res
+    }
+    pub fn to_tile(&self, palette: &Palette) -> Tile {
+        let mut res = Tile::default();
+        for (x, y) in tile_rect() {
+            let color = self.inner[y * TILE_SIZE + x].and_then(|i| palette.get(i));
+            if let Some(d) = res.get_mut_xy(x, y) {
+                *d = color;
+            }
+        }
+        res
+    }
+    /// Draw the tile into `buf` with its upper left corner at `(x, y)`,
+    /// looking up colors in `palette`.
+    pub fn draw<B, I>(&self, buf: &mut B, x: i16, y: i16, alpha: Alpha, palette: &Palette)
+    where
+        B: GetMut2D<Item = I>,
+        I: Blend,
+    {
+        blit_tile(buf, x, y, alpha, |p, _| self.color_at(p, palette));
+    }
+    /// Draw the tile, looking up colors in the palette of each scanline
+    pub fn draw_scanlines<B, I>(
+        &self,
+        buf: &mut B,
+        x: i16,
+        y: i16,
+        alpha: Alpha,
+        lines: &ScanlinePalettes,
+    ) where
+        B: GetMut2D<Item = I>,
+        I: Blend,
+    {
+        blit_tile(buf, x, y, alpha, |p, (_, sy)| self.color_at(p, lines.palette_at(sy)));
+    }
+    fn color_at(&self, (x, y): (usize, usize), palette: &Palette) -> Option<Color> {
+        self.inner[y * TILE_SIZE + x].and_then(|i| palette.get(i))
+    }
+}
+
+impl Get2D for IndexedTile {
+    type Item = Option<u8>;
+    fn get_xy<T: ToPrimitive>(&self, x: T, y: T) -> Option<&Option<u8>> {
+        let (x, y) = (x.to_usize()?, y.to_usize()?);
+        if TILE_SIZE <= x || TILE_SIZE <= y {
+            return None;
+        }
+        Some(&self.inner[y * TILE_SIZE + x])
+    }
+}
+
+impl GetMut2D for IndexedTile {
+    type Item = Option<u8>;
+    fn get_mut_xy<T: ToPrimitive>(&mut self, x: T, y: T) -> Option<&mut Option<u8>> {
+        let (x, y) = (x.to_usize()?, y.to_usize()?);
+        if TILE_SIZE <= x || TILE_SIZE <= y {
+            return None;
+        }
+        Some(&mut self.inner[y * TILE_SIZE + x])
+    }
+}
+
+/// Frame whose tiles are palette-indexed
+#[derive(Clone, Debug)]
+pub struct IndexedFrame {
+    name: String,
+    mesh: MeshTree,
+    tiles: Vec<(IndexedTile, TilePoint)>,
+    alpha: Alpha,
+}
+
+impl IndexedFrame {
+    /// Quantize the image into `palette` while loading
+    pub fn from_buf(
+        buf: &RgbaImage,
+        name: &str,
+        palette: &Palette,
+    ) -> Result<IndexedFrame, FrameError> {
+        let frame = Frame::from_buf(buf, name)?;
+        Ok(IndexedFrame::from_frame(&frame, palette))
+    }
+    pub fn from_frame(frame: &Frame, palette: &Palette) -> IndexedFrame {
+        let tiles = frame
+            .tiles
+            .iter()
+            .map(|(tile, p)| (IndexedTile::from_tile(tile, palette), *p))
+            .collect();
+        IndexedFrame {
+            name: frame.name.clone(),
+            mesh: frame.mesh.clone(),
+            tiles: tiles,
+            alpha: frame.alpha,
+        }
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn mesh(&self) -> &MeshTree {
+        &self.mesh
+    }
+    /// Draw the frame with its upper left corner at `origin`.
+    /// Pass another palette to swap colors at render time.
+    pub fn draw<B, I>(&self, buf: &mut B, origin: DotPoint, palette: &Palette)
+    where
+        B: GetMut2D<Item = I>,
+        I: Blend,
+    {
+        blit_frame(buf, &self.tiles, origin, self.alpha, |tile, p, _| tile.color_at(p, palette));
+    }
+    /// Draw the frame, switching palettes at scanlines
+    pub fn draw_scanlines<B, I>(&self, buf: &mut B, origin: DotPoint, lines: &ScanlinePalettes)
+    where
+        B: GetMut2D<Item = I>,
+        I: Blend,
+    {
+        blit_frame(buf, &self.tiles, origin, self.alpha, |tile, p, (_, sy)| {
+            tile.color_at(p, lines.palette_at(sy))
+        });
+    }
+}
+
+#[cfg(test)]
+mod palette_test {
+    use super::*;
+    use euclid::point2;
+    use image::Rgba;
+    use testutils::{load_frame, load_img};
+    #[test]
+    fn load_files() {
+        let hex = Palette::load(Path::new("../test-assets/palette/sweetie16.hex")).unwrap();
+        let gpl = Palette::load(Path::new("../test-assets/palette/sweetie16.gpl")).unwrap();
+        assert_eq!(hex.len(), 16);
+        assert_eq!(hex, gpl);
+        assert_eq!(hex.get(0), Some(Color { r: 26, g: 28, b: 44 }));
+        assert_eq!(hex.get(16), None);
+        assert!(Palette::from_hex("12345g").is_err());
+        assert!(Palette::from_gpl("1 2 3").is_err());
+    }
+    #[test]
+    fn palette_from_image() {
+        let img = load_img("../test-assets/chara2.png");
+        let palette = Palette::from_buf(&img).unwrap();
+        assert!(img.pixels()
+            .filter_map(Color::from_rgba)
+            .all(|c| palette.get(palette.nearest(c)) == Some(c)));
+        let mut many = RgbaImage::new(300, 1);
+        (0..300).for_each(|x| {
+            let p = Rgba {
+                data: [x as u8, (x / 256) as u8, 0, 255],
+            };
+            many.put_pixel(x, 0, p);
+        });
+        match Palette::from_buf(&many) {
+            Err(PaletteError::TooMany(_)) => {}
+            r => panic!("{:?}", r),
+        }
+    }
+    #[test]
+    fn nearest_color() {
+        let black = Color::black();
+        let white = Color::white();
+        let palette = Palette::new(vec![black, white]).unwrap();
+        assert_eq!(palette.nearest(Color { r: 100, g: 100, b: 100 }), 0);
+        assert_eq!(palette.nearest(Color { r: 200, g: 100, b: 200 }), 1);
+    }
+    #[test]
+    fn indexed_frame_draw() {
+        let img = load_img("../test-assets/chara2.png");
+        let palette = Palette::from_buf(&img).unwrap();
+        let frame = load_frame("../test-assets/chara2.png");
+        let indexed = IndexedFrame::from_frame(&frame, &palette);
+        let white = Rgba { data: [255; 4] };
+        let mut expected = RgbaImage::from_pixel(40, 40, white);
+        frame.draw(&mut expected, point2(3, 5));
+        let mut img = RgbaImage::from_pixel(40, 40, white);
+        indexed.draw(&mut img, point2(3, 5), &palette);
+        assert_eq!(img, expected);
+        // damage flash
+        let mut flash = palette.clone();
+        flash.fill(Color::black());
+        let mut img = RgbaImage::from_pixel(40, 40, white);
+        indexed.draw(&mut img, point2(3, 5), &flash);
+        assert!(img.pixels().all(|p| p[0] == p[1] && p[1] == p[2]));
+        assert_ne!(img, expected);
+    }
+}
@@ -0,0 +1,200 @@
+//! palette cycling, fading and per-scanline palettes
+
+use palette::Palette;
+use schedule::{ScheduleType, Scheduler};
+use tile::Color;
+use Clock;
+
+/// Effect applied to the palette by PaletteEffects
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PaletteEffect {
+    /// rotate entries from `first` to `last`(inclusive)
+    Cycle { first: u8, last: u8 },
+    /// move all colors toward `target`, reaching it at `end`
+    Fade { target: Color, end: Clock },
+}
+
+impl PaletteEffect {
+    fn apply(self, palette: &mut Palette, now: Clock) {
+        match self {
+            PaletteEffect::Cycle { first, last } => palette.rotate(first, last),
+            PaletteEffect::Fade { target, end } => {
+                let remaining = (end.saturating_sub(now) + 1) as i32;
+                let step = |c: u8, t: u8| {
+                    let (c, t) = (i32::from(c), i32::from(t));
+                    (c + (t - c) / remaining) as u8
+                };
+                (0..palette.len()).for_each(|i| {
+                    let i = i as u8;
+                    if let Some(c) = palette.get(i) {
+                        let next = Color {
+                            r: step(c.r, target.r),
+                            g: step(c.g, target.g),
+                            b: step(c.b, target.b),
+                        };
+                        palette.set(i, next);
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// Palette animated by scheduled effects
+pub struct PaletteEffects {
+    palette: Palette,
+    scheduler: Scheduler<PaletteEffect>,
+}
+
+impl PaletteEffects {
+    pub fn new(palette: Palette) -> PaletteEffects {
+        PaletteEffects {
+            palette: palette,
+            scheduler: Scheduler::new(),
+        }
+    }
+    /// Rotate entries from `first` to `last`(inclusive) every `interval` ticks,
+    /// starting at `start`(e.g. water and lava animation). `interval` of 0 is ignored.
+    pub fn cycle(&mut self, first: u8, last: u8, start: Clock, interval: Clock) -> &mut Self {
+        if interval == 0 {
+            return self;
+        }
+        let typ = ScheduleType::repeated_once(start, interval);
+        let effect = PaletteEffect::Cycle {
+            first: first,
+            last: last,
+        };
+        self.scheduler.push(typ, effect);
+        self
+    }
+    /// Fade the whole palette to `target` in `duration` ticks from `start`
+    pub fn fade(&mut self, target: Color, start: Clock, duration: Clock) -> &mut Self {
+        if duration == 0 {
+            return self;
+        }
+        let end = start + duration - 1;
+        let typ = ScheduleType::span(start, duration);
+        let effect = PaletteEffect::Fade {
+            target: target,
+            end: end,
+        };
+        self.scheduler.push(typ, effect);
+        self
+    }
+    /// Apply effects scheduled until `now`. Call this once per tick.
+    pub fn update(&mut self, now: Clock) {
+        for effect in self.scheduler.pop(now) {
+            effect.apply(&mut self.palette, now);
+        }
+    }
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+}
+
+/// Palettes switched at scanlines(y in the screen buffer),
+/// for raster effects like water surfaces.
+#[derive(Clone, Debug)]
+pub struct ScanlinePalettes {
+    base: Palette,
+    /// (first scanline, palette), sorted by scanline
+    splits: Vec<(i16, Palette)>,
+}
+
+impl ScanlinePalettes {
+    pub fn new(base: Palette) -> ScanlinePalettes {
+        ScanlinePalettes {
+            base: base,
+            splits: Vec::new(),
+        }
+    }
+    /// Use `palette` from scanline `y` until the next split
+    pub fn split(&mut self, y: i16, palette: Palette) -> &mut Self {
+        match self.splits.binary_search_by_key(&y, |&(sy, _)| sy) {
+            Ok(i) => self.splits[i].1 = palette,
+            Err(i) => self.splits.insert(i, (y, palette)),
+        }
+        self
+    }
+    pub fn clear(&mut self) {
+        self.splits.clear();
+    }
+    pub fn palette_at(&self, y: i16) -> &Palette {
+        match self.splits.binary_search_by_key(&y, |&(sy, _)| sy) {
+            Ok(i) => &self.splits[i].1,
+            Err(0) => &self.base,
+            Err(i) => &self.splits[i - 1].1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod palette_effect_test {
+    use super::*;
+    use euclid::point2;
+    use image::{Rgba, RgbaImage};
+    use palette::IndexedFrame;
+    use testutils::{load_frame, load_img};
+    fn gray(v: u8) -> Color {
+        Color { r: v, g: v, b: v }
+    }
+    #[test]
+    fn cycle() {
+        let palette = Palette::new((0..4).map(gray).collect()).unwrap();
+        let mut effects = PaletteEffects::new(palette);
+        effects.cycle(0, 2, 2, 2);
+        (0..3).for_each(|i| effects.update(i));
+        assert_eq!(effects.palette().colors(), &[gray(2), gray(0), gray(1), gray(3)]);
+        (3..5).for_each(|i| effects.update(i));
+        assert_eq!(effects.palette().colors(), &[gray(1), gray(2), gray(0), gray(3)]);
+    }
+    #[test]
+    fn cycle_zero_interval() {
+        let palette = Palette::new((0..4).map(gray).collect()).unwrap();
+        let mut effects = PaletteEffects::new(palette);
+        effects.cycle(0, 2, 0, 0);
+        (0..3).for_each(|i| effects.update(i));
+        assert_eq!(effects.palette().colors(), &[gray(0), gray(1), gray(2), gray(3)]);
+    }
+    #[test]
+    fn fade() {
+        let palette = Palette::new(vec![Color::black(), Color::white()]).unwrap();
+        let mut effects = PaletteEffects::new(palette);
+        effects.fade(gray(100), 1, 4);
+        effects.update(0);
+        assert_eq!(effects.palette().get(0), Some(Color::black()));
+        effects.update(1);
+        let c = effects.palette().get(0).unwrap();
+        assert!(0 < c.r && c.r < 100);
+        (2..5).for_each(|i| effects.update(i));
+        assert_eq!(effects.palette().colors(), &[gray(100), gray(100)]);
+        effects.update(5);
+        assert_eq!(effects.palette().colors(), &[gray(100), gray(100)]);
+    }
+    #[test]
+    fn scanlines() {
+        let img = load_img("../test-assets/chara2.png");
+        let palette = Palette::from_buf(&img).unwrap();
+        let frame = load_frame("../test-assets/chara2.png");
+        let indexed = IndexedFrame::from_frame(&frame, &palette);
+        let mut dark = palette.clone();
+        dark.fill(Color::black());
+        let mut lines = ScanlinePalettes::new(palette.clone());
+        lines.split(16, dark);
+        assert_eq!(lines.palette_at(15), &palette);
+        assert_eq!(lines.palette_at(40), lines.palette_at(16));
+        let white = Rgba { data: [255; 4] };
+        let mut expected = RgbaImage::from_pixel(32, 32, white);
+        indexed.draw(&mut expected, point2(0, 0), &palette);
+        let mut img = RgbaImage::from_pixel(32, 32, white);
+        indexed.draw_scanlines(&mut img, point2(0, 0), &lines);
+        for (x, y, p) in img.enumerate_pixels() {
+            let e = expected.get_pixel(x, y);
+            if y < 16 {
+                assert_eq!(p, e);
+            } else if e != &white {
+                assert!(p[0] == p[1] && p[1] == p[2]);
+            }
+        }
+    }
+}
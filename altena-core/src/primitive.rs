@@ -0,0 +1,524 @@
+//! 2D primitives(lines, rectangles, circles, ...) drawn on dot buffers
+
+use euclid::point2;
+use rect_iter::GetMut2D;
+
+use std::cmp::{max, min};
+use std::collections::HashSet;
+
+use frame::dottypes::*;
+use tile::{Alpha, Blend, Color};
+
+/// Blend `color` into every point once, even if `points` has duplicates.
+fn plot_points<B, I>(buf: &mut B, mut points: Vec<DotPoint>, color: Color, alpha: Alpha)
+where
+    B: GetMut2D<Item = I>,
+    I: Blend,
+{
+    if alpha.is_trans() {
+        return;
+    }
+    points.sort_by_key(|p| (p.y, p.x));
+    points.dedup();
+    for p in points {
+        if let Some(b) = buf.get_mut_xy(p.x, p.y) {
+            b.blend(color, alpha);
+        }
+    }
+}
+
+/// Size of a rectangular buffer, found by binary search since GetMut2D doesn't tell it
+pub(crate) fn buf_size<B, I>(buf: &mut B) -> (i16, i16)
+where
+    B: GetMut2D<Item = I>,
+{
+    // the number of leading indices for which `has` is true
+    let len = |has: &mut dyn FnMut(i16) -> bool| {
+        let (mut lo, mut hi) = (0i32, i32::from(i16::max_value()));
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if has(mid as i16) {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        lo as i16
+    };
+    let w = len(&mut |x| buf.get_mut_xy(x, 0).is_some());
+    let h = len(&mut |y| buf.get_mut_xy(0, y).is_some());
+    (w, h)
+}
+
+/// Size of the buffer in i32, to clip shapes computed in i32
+fn bounds<B, I>(buf: &mut B) -> (i32, i32)
+where
+    B: GetMut2D<Item = I>,
+{
+    let (w, h) = buf_size(buf);
+    (i32::from(w), i32::from(h))
+}
+
+/// Points of a shape, which calls the callback with each point
+type Shape<'a> = &'a dyn Fn(&mut dyn FnMut(i32, i32));
+
+/// Points of `shape` in the buffer of `size`
+fn clipped(size: (i32, i32), shape: Shape) -> Vec<DotPoint> {
+    let mut res = vec![];
+    shape(&mut |x: i32, y: i32| {
+        if 0 <= x && x < size.0 && 0 <= y && y < size.1 {
+            res.push(point2(x as i16, y as i16));
+        }
+    });
+    res
+}
+
+/// Points of the line(inclusive) by Bresenham's algorithm
+fn line_dots(from: (i32, i32), to: (i32, i32), f: &mut dyn FnMut(i32, i32)) {
+    let (x1, y1) = to;
+    let (mut x, mut y) = from;
+    let (dx, dy) = ((x1 - x).abs(), (y1 - y).abs());
+    let (sx, sy) = (if x < x1 { 1 } else { -1 }, if y < y1 { 1 } else { -1 });
+    let mut err = dx - dy;
+    loop {
+        f(x, y);
+        if x == x1 && y == y1 {
+            return;
+        }
+        let e2 = 2 * err;
+        if e2 > -dy {
+            err -= dy;
+            x += sx;
+        }
+        if e2 < dx {
+            err += dx;
+            y += sy;
+        }
+    }
+}
+
+fn to_i32(p: DotPoint) -> (i32, i32) {
+    (i32::from(p.x), i32::from(p.y))
+}
+
+/// Points on the outline of the circle by the midpoint algorithm
+fn circle_dots(center: DotPoint, radius: i16, f: &mut dyn FnMut(i32, i32)) {
+    if radius < 0 {
+        return;
+    }
+    let (cx, cy) = to_i32(center);
+    let radius = i32::from(radius);
+    let (mut x, mut y, mut d) = (radius, 0, 1 - radius);
+    while x >= y {
+        for &(px, py) in &[(x, y), (y, x), (-y, x), (-x, y)] {
+            f(cx + px, cy + py);
+            f(cx + px, cy - py);
+        }
+        y += 1;
+        if d < 0 {
+            d += 2 * y + 1;
+        } else {
+            x -= 1;
+            d += 2 * (y - x) + 1;
+        }
+    }
+}
+
+/// Points on the outline of the ellipse by the midpoint algorithm
+fn ellipse_dots(center: DotPoint, rx: i16, ry: i16, f: &mut dyn FnMut(i32, i32)) {
+    if rx < 0 || ry < 0 {
+        return;
+    }
+    let (cx, cy) = to_i32(center);
+    let (rx, ry) = (i32::from(rx), i32::from(ry));
+    // flat ellipses are lines of 2 * r + 1 dots
+    if rx == 0 || ry == 0 {
+        return line_dots((cx - rx, cy - ry), (cx + rx, cy + ry), f);
+    }
+    let (a2, b2) = (f64::from(rx) * f64::from(rx), f64::from(ry) * f64::from(ry));
+    let (mut x, mut y) = (0i32, ry);
+    let (mut px, mut py) = (0.0, 2.0 * a2 * f64::from(y));
+    let mut push4 = |x: i32, y: i32| {
+        for &(sx, sy) in &[(1, 1), (-1, 1), (1, -1), (-1, -1)] {
+            f(cx + sx * x, cy + sy * y);
+        }
+    };
+    // region 1: slope > -1
+    let mut p = b2 - a2 * f64::from(ry) + 0.25 * a2;
+    while px < py {
+        push4(x, y);
+        x += 1;
+        px += 2.0 * b2;
+        if p < 0.0 {
+            p += b2 + px;
+        } else {
+            y -= 1;
+            py -= 2.0 * a2;
+            p += b2 + px - py;
+        }
+    }
+    // region 2: slope <= -1
+    let (fx, fy) = (f64::from(x) + 0.5, f64::from(y) - 1.0);
+    let mut p = b2 * fx * fx + a2 * fy * fy - a2 * b2;
+    while y >= 0 {
+        push4(x, y);
+        y -= 1;
+        py -= 2.0 * a2;
+        if p > 0.0 {
+            p += a2 - py;
+        } else {
+            x += 1;
+            px += 2.0 * b2;
+            p += a2 - py + px;
+        }
+    }
+}
+
+/// Fill between the leftmost and rightmost points of each row of a convex
+/// `outline`, clipped to the buffer of `size`
+fn fill_convex(size: (i32, i32), outline: Shape) -> Vec<DotPoint> {
+    let (w, h) = size;
+    let mut spans = vec![(i32::max_value(), i32::min_value()); max(h, 0) as usize];
+    outline(&mut |x: i32, y: i32| {
+        if 0 <= y && y < h {
+            let span = &mut spans[y as usize];
+            *span = (min(span.0, x), max(span.1, x));
+        }
+    });
+    spans
+        .into_iter()
+        .enumerate()
+        .flat_map(|(y, (x0, x1))| {
+            (max(x0, 0)..=min(x1, w - 1)).map(move |x| point2(x as i16, y as i16))
+        })
+        .collect()
+}
+
+/// Points of the polygon outline(closed)
+fn polygon_dots(vertices: &[DotPoint], f: &mut dyn FnMut(i32, i32)) {
+    let n = vertices.len();
+    for i in 0..n {
+        line_dots(to_i32(vertices[i]), to_i32(vertices[(i + 1) % n]), f);
+    }
+}
+
+pub fn pixel<B, I>(buf: &mut B, p: DotPoint, color: Color, alpha: Alpha)
+where
+    B: GetMut2D<Item = I>,
+    I: Blend,
+{
+    plot_points(buf, vec![p], color, alpha);
+}
+
+pub fn line<B, I>(buf: &mut B, from: DotPoint, to: DotPoint, color: Color, alpha: Alpha)
+where
+    B: GetMut2D<Item = I>,
+    I: Blend,
+{
+    let size = bounds(buf);
+    let points = clipped(size, &|f| line_dots(to_i32(from), to_i32(to), f));
+    plot_points(buf, points, color, alpha);
+}
+
+/// Outline of `rect`
+pub fn rect<B, I>(buf: &mut B, rect: DotRect, color: Color, alpha: Alpha)
+where
+    B: GetMut2D<Item = I>,
+    I: Blend,
+{
+    if rect.size.width <= 0 || rect.size.height <= 0 {
+        return;
+    }
+    let (x0, y0) = (rect.min_x(), rect.min_y());
+    let (x1, y1) = (rect.max_x() - 1, rect.max_y() - 1);
+    let corners = [point2(x0, y0), point2(x1, y0), point2(x1, y1), point2(x0, y1)];
+    polygon(buf, &corners, color, alpha);
+}
+
+pub fn fill_rect<B, I>(buf: &mut B, rect: DotRect, color: Color, alpha: Alpha)
+where
+    B: GetMut2D<Item = I>,
+    I: Blend,
+{
+    if alpha.is_trans() {
+        return;
+    }
+    let (w, h) = buf_size(buf);
+    let rect = match rect.intersection(&DotRect::new(point2(0, 0), DotSize::new(w, h))) {
+        Some(r) => r,
+        None => return,
+    };
+    for y in rect.min_y()..rect.max_y() {
+        for x in rect.min_x()..rect.max_x() {
+            if let Some(b) = buf.get_mut_xy(x, y) {
+                b.blend(color, alpha);
+            }
+        }
+    }
+}
+
+pub fn circle<B, I>(buf: &mut B, center: DotPoint, radius: i16, color: Color, alpha: Alpha)
+where
+    B: GetMut2D<Item = I>,
+    I: Blend,
+{
+    let size = bounds(buf);
+    let points = clipped(size, &|f| circle_dots(center, radius, f));
+    plot_points(buf, points, color, alpha);
+}
+
+pub fn fill_circle<B, I>(buf: &mut B, center: DotPoint, radius: i16, color: Color, alpha: Alpha)
+where
+    B: GetMut2D<Item = I>,
+    I: Blend,
+{
+    let size = bounds(buf);
+    let points = fill_convex(size, &|f| circle_dots(center, radius, f));
+    plot_points(buf, points, color, alpha);
+}
+
+pub fn ellipse<B, I>(buf: &mut B, center: DotPoint, rx: i16, ry: i16, color: Color, alpha: Alpha)
+where
+    B: GetMut2D<Item = I>,
+    I: Blend,
+{
+    let size = bounds(buf);
+    let points = clipped(size, &|f| ellipse_dots(center, rx, ry, f));
+    plot_points(buf, points, color, alpha);
+}
+
+pub fn fill_ellipse<B, I>(
+    buf: &mut B,
+    center: DotPoint,
+    rx: i16,
+    ry: i16,
+    color: Color,
+    alpha: Alpha,
+) where
+    B: GetMut2D<Item = I>,
+    I: Blend,
+{
+    let size = bounds(buf);
+    let points = fill_convex(size, &|f| ellipse_dots(center, rx, ry, f));
+    plot_points(buf, points, color, alpha);
+}
+
+/// Outline of the polygon. The last vertex is connected to the first one.
+pub fn polygon<B, I>(buf: &mut B, vertices: &[DotPoint], color: Color, alpha: Alpha)
+where
+    B: GetMut2D<Item = I>,
+    I: Blend,
+{
+    let size = bounds(buf);
+    let points = clipped(size, &|f| polygon_dots(vertices, f));
+    plot_points(buf, points, color, alpha);
+}
+
+/// Fill the polygon by the even-odd rule. The outline is also filled.
+pub fn fill_polygon<B, I>(buf: &mut B, vertices: &[DotPoint], color: Color, alpha: Alpha)
+where
+    B: GetMut2D<Item = I>,
+    I: Blend,
+{
+    let (w, h) = bounds(buf);
+    let mut points = clipped((w, h), &|f| polygon_dots(vertices, f));
+    let (min_y, max_y) = match (
+        vertices.iter().map(|p| p.y).min(),
+        vertices.iter().map(|p| p.y).max(),
+    ) {
+        (Some(lo), Some(hi)) => (max(lo, 0), min(i32::from(hi), h - 1) as i16),
+        _ => return,
+    };
+    let n = vertices.len();
+    for y in min_y..=max_y {
+        let yc = f32::from(y);
+        let mut xs: Vec<f32> = (0..n)
+            .filter_map(|i| {
+                let (p0, p1) = (vertices[i], vertices[(i + 1) % n]);
+                let (y0, y1) = (f32::from(p0.y), f32::from(p1.y));
+                // half-open to count each vertex once
+                if (y0 <= yc && yc < y1) || (y1 <= yc && yc < y0) {
+                    let t = (yc - y0) / (y1 - y0);
+                    Some(f32::from(p0.x) + t * (f32::from(p1.x) - f32::from(p0.x)))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        xs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        for pair in xs.chunks(2) {
+            if let [x0, x1] = pair {
+                let (x0, x1) = (max(x0.ceil() as i32, 0), min(x1.floor() as i32, w - 1));
+                points.extend((x0..=x1).map(|x| point2(x as i16, y)));
+            }
+        }
+    }
+    plot_points(buf, points, color, alpha);
+}
+
+/// Fill the 4-connected area which has the same value as `seed`
+pub fn flood_fill<B, I>(buf: &mut B, seed: DotPoint, color: Color, alpha: Alpha)
+where
+    B: GetMut2D<Item = I>,
+    I: Blend + Clone + PartialEq,
+{
+    let target = match buf.get_mut_xy(seed.x, seed.y) {
+        Some(b) => b.clone(),
+        None => return,
+    };
+    let mut visited = HashSet::new();
+    let mut stack = vec![(seed.x, seed.y)];
+    let mut region = vec![];
+    while let Some((x, y)) = stack.pop() {
+        if !visited.insert((x, y)) {
+            continue;
+        }
+        if buf.get_mut_xy(x, y).map_or(true, |b| *b != target) {
+            continue;
+        }
+        region.push(point2(x, y));
+        let neighbors = [
+            x.checked_sub(1).map(|x| (x, y)),
+            x.checked_add(1).map(|x| (x, y)),
+            y.checked_sub(1).map(|y| (x, y)),
+            y.checked_add(1).map(|y| (x, y)),
+        ];
+        stack.extend(neighbors.iter().filter_map(|n| *n));
+    }
+    plot_points(buf, region, color, alpha);
+}
+
+#[cfg(test)]
+mod primitive_test {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+    use rect_iter::Get2D;
+    fn white_img() -> RgbaImage {
+        RgbaImage::from_pixel(32, 32, Rgba { data: [255; 4] })
+    }
+    fn is_black(img: &RgbaImage, x: u32, y: u32) -> bool {
+        img.get_xy(x, y).map_or(false, |p| p[0] == 0)
+    }
+    fn count_black(img: &RgbaImage) -> usize {
+        img.pixels().filter(|p| p[0] == 0).count()
+    }
+    const SOLID: Alpha = Alpha(15);
+    #[test]
+    fn draw_line() {
+        let mut img = white_img();
+        line(&mut img, point2(1, 1), point2(6, 3), Color::black(), SOLID);
+        assert_eq!(count_black(&img), 6);
+        assert!(is_black(&img, 1, 1) && is_black(&img, 6, 3));
+        let mut rev = white_img();
+        line(&mut rev, point2(6, 3), point2(1, 1), Color::black(), SOLID);
+        assert_eq!(count_black(&rev), 6);
+        let mut clipped = white_img();
+        line(&mut clipped, point2(-10, 0), point2(40, 0), Color::black(), SOLID);
+        assert_eq!(count_black(&clipped), 32);
+    }
+    #[test]
+    fn draw_rect() {
+        let mut img = white_img();
+        let r = DotRect::new(point2(2, 2), DotSize::new(5, 4));
+        rect(&mut img, r, Color::black(), SOLID);
+        assert_eq!(count_black(&img), 14);
+        fill_rect(&mut img, r, Color::black(), SOLID);
+        assert_eq!(count_black(&img), 20);
+        let huge = DotRect::new(point2(-20000, -20000), DotSize::new(30000, 30000));
+        fill_rect(&mut img, huge, Color::black(), SOLID);
+        assert_eq!(count_black(&img), 32 * 32);
+        let mut img = white_img();
+        let outside = DotRect::new(point2(100, -5), DotSize::new(30000, 30000));
+        fill_rect(&mut img, outside, Color::black(), SOLID);
+        assert_eq!(count_black(&img), 0);
+    }
+    #[test]
+    fn draw_circle() {
+        let mut img = white_img();
+        circle(&mut img, point2(10, 10), 5, Color::black(), SOLID);
+        assert!(is_black(&img, 15, 10) && is_black(&img, 10, 5));
+        assert!(!is_black(&img, 10, 10));
+        let outline = count_black(&img);
+        fill_circle(&mut img, point2(10, 10), 5, Color::black(), SOLID);
+        assert!(is_black(&img, 10, 10));
+        assert!(count_black(&img) > outline);
+    }
+    #[test]
+    fn draw_ellipse() {
+        let mut img = white_img();
+        ellipse(&mut img, point2(10, 10), 6, 3, Color::black(), SOLID);
+        assert!(is_black(&img, 16, 10) && is_black(&img, 4, 10));
+        assert!(is_black(&img, 10, 13) && is_black(&img, 10, 7));
+        assert!(!is_black(&img, 10, 10) && !is_black(&img, 10, 14));
+        fill_ellipse(&mut img, point2(10, 10), 6, 3, Color::black(), SOLID);
+        assert!(is_black(&img, 10, 10) && !is_black(&img, 10, 14));
+    }
+    #[test]
+    fn draw_flat_ellipse() {
+        let mut img = white_img();
+        ellipse(&mut img, point2(10, 10), 4, 0, Color::black(), SOLID);
+        assert_eq!(count_black(&img), 9);
+        assert!(is_black(&img, 6, 10) && is_black(&img, 14, 10));
+        let mut img = white_img();
+        fill_ellipse(&mut img, point2(10, 10), 0, 3, Color::black(), SOLID);
+        assert_eq!(count_black(&img), 7);
+        assert!(is_black(&img, 10, 7) && is_black(&img, 10, 13));
+        let mut img = white_img();
+        ellipse(&mut img, point2(10, 10), 0, 0, Color::black(), SOLID);
+        assert_eq!(count_black(&img), 1);
+    }
+    #[test]
+    fn draw_huge_shapes() {
+        let max = i16::max_value();
+        let mut img = white_img();
+        fill_circle(&mut img, point2(16, 16), max, Color::black(), SOLID);
+        assert_eq!(count_black(&img), 32 * 32);
+        // the center is far out of the buffer, and the outline crosses it
+        let mut img = white_img();
+        circle(&mut img, point2(max, 16), max - 16, Color::black(), SOLID);
+        assert!(is_black(&img, 16, 16));
+        let mut img = white_img();
+        fill_ellipse(&mut img, point2(max, 16), max, 10, Color::black(), SOLID);
+        assert!(is_black(&img, 0, 16) && is_black(&img, 31, 16));
+        assert!(!is_black(&img, 0, 0));
+        let mut img = white_img();
+        ellipse(&mut img, point2(16, 20), max, 0, Color::black(), SOLID);
+        assert_eq!(count_black(&img), 32);
+        let (lo, hi) = (i16::min_value(), max);
+        let tri = [point2(lo, lo), point2(hi, lo), point2(0, hi)];
+        let mut img = white_img();
+        fill_polygon(&mut img, &tri, Color::black(), SOLID);
+        assert_eq!(count_black(&img), 32 * 32);
+    }
+    #[test]
+    fn draw_polygon() {
+        let tri = [point2(2, 2), point2(12, 2), point2(2, 12)];
+        let mut img = white_img();
+        polygon(&mut img, &tri, Color::black(), SOLID);
+        assert!(!is_black(&img, 4, 4));
+        fill_polygon(&mut img, &tri, Color::black(), SOLID);
+        assert!(is_black(&img, 4, 4) && is_black(&img, 12, 2));
+        assert!(!is_black(&img, 10, 10));
+    }
+    #[test]
+    fn flood() {
+        let mut img = white_img();
+        let r = DotRect::new(point2(2, 2), DotSize::new(6, 6));
+        rect(&mut img, r, Color::black(), SOLID);
+        let red = Color { r: 255, g: 0, b: 0 };
+        flood_fill(&mut img, point2(4, 4), red, SOLID);
+        assert_eq!(img.get_pixel(4, 4)[1], 0);
+        assert_eq!(img.get_pixel(3, 3)[1], 0);
+        assert_eq!(img.get_pixel(1, 1)[1], 255);
+        assert_eq!(img.pixels().filter(|p| p[0] == 255 && p[1] == 0).count(), 16);
+    }
+    #[test]
+    fn blend_once() {
+        let mut img = white_img();
+        circle(&mut img, point2(10, 10), 7, Color::black(), Alpha(8));
+        let mut values: Vec<_> = img.pixels().map(|p| p[0]).collect();
+        values.sort();
+        values.dedup();
+        assert_eq!(values.len(), 2);
+    }
+}
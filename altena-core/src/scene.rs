@@ -1,8 +1,11 @@
 //! Sprite and scene management
 
 use std::collections::HashMap;
+use camera::Camera;
 use frame::Frame;
 use frame::dottypes::*;
+use rect_iter::GetMut2D;
+use tile::Blend;
 
 /// Sprite is a set of `Drawing objects` which an object has
 pub struct Sprite {
@@ -14,6 +17,97 @@ pub struct Sprite {
     childlen: HashMap<String, Sprite>,
     /// draw priority
     priority: u8,
+    /// world or screen
+    anchor: Anchor,
+    /// frame drawn at the sprite's position
+    frame: Option<Frame>,
+}
+
+impl Sprite {
+    /// Root sprite(window), which has no frame
+    pub fn root(name: &str) -> Sprite {
+        Sprite::with_type(name, NodeType::Root, None)
+    }
+    /// Sprite at `p` in its parent's coordinate
+    pub fn child(name: &str, p: DotPoint, frame: Option<Frame>) -> Sprite {
+        Sprite::with_type(name, NodeType::Child(p), frame)
+    }
+    fn with_type(name: &str, typ: NodeType, frame: Option<Frame>) -> Sprite {
+        Sprite {
+            name: name.to_owned(),
+            typ: typ,
+            childlen: HashMap::new(),
+            priority: 0,
+            anchor: Anchor::World,
+            frame: frame,
+        }
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// Sprites with lower priority are drawn first
+    pub fn priority(&mut self, p: u8) -> &mut Sprite {
+        self.priority = p;
+        self
+    }
+    /// Anchor of the sprite and its children
+    pub fn anchor(&mut self, anchor: Anchor) -> &mut Sprite {
+        self.anchor = anchor;
+        self
+    }
+    /// Add `child`, replacing the child with the same name
+    pub fn add_child(&mut self, child: Sprite) -> &mut Sprite {
+        self.childlen.insert(child.name.clone(), child);
+        self
+    }
+    pub fn child_mut(&mut self, name: &str) -> Option<&mut Sprite> {
+        self.childlen.get_mut(name)
+    }
+    /// Draw the sprite and its children into `buf`(screen coordinates) seen by `camera`.
+    pub fn draw<B, I>(&self, buf: &mut B, camera: &Camera)
+    where
+        B: GetMut2D<Item = I>,
+        I: Blend,
+    {
+        self.draw_(buf, camera, DotPoint::new(0, 0), Anchor::World);
+    }
+    fn draw_<B, I>(&self, buf: &mut B, camera: &Camera, parent: DotPoint, anchor: Anchor)
+    where
+        B: GetMut2D<Item = I>,
+        I: Blend,
+    {
+        // screen anchored sprites make their children screen anchored too
+        let anchor = if anchor == Anchor::Screen { anchor } else { self.anchor };
+        let p = match self.typ {
+            NodeType::Root => parent,
+            NodeType::Child(p) => parent + p.to_vector(),
+        };
+        if let Some(ref frame) = self.frame {
+            frame.draw(buf, self.screen_point(p, anchor, camera));
+        }
+        let mut children: Vec<_> = self.childlen.values().collect();
+        children.sort_by(|a, b| (a.priority, &a.name).cmp(&(b.priority, &b.name)));
+        for child in children {
+            child.draw_(buf, camera, p, anchor);
+        }
+    }
+    /// Convert the sprite's position into screen coordinates.
+    /// HUD sprites(Anchor::Screen) don't move with the camera.
+    fn screen_point(&self, p: DotPoint, anchor: Anchor, camera: &Camera) -> DotPoint {
+        match anchor {
+            Anchor::World => camera.world_to_screen(p),
+            Anchor::Screen => p,
+        }
+    }
+}
+
+/// Coordinate system the sprite belongs to
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Anchor {
+    /// moves with the camera
+    World,
+    /// fixed on the screen(e.g. HUD)
+    Screen,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -31,3 +125,33 @@ pub enum SpriteAction {
     Rotate(u16),
     Scale(u8),
 }
+
+#[cfg(test)]
+mod scene_test {
+    use super::*;
+    use euclid::point2;
+    use image::{Rgba, RgbaImage};
+    use testutils::load_frame;
+    #[test]
+    fn anchor() {
+        let white = Rgba { data: [255; 4] };
+        let mut camera = Camera::new();
+        camera.move_to(point2(100, 50));
+        let mut root = Sprite::root("root");
+        let chara = load_frame("../test-assets/chara1.png");
+        root.add_child(Sprite::child("player", point2(110, 60), Some(chara)));
+        let mut hud = Sprite::child("hud", point2(200, 100), None);
+        hud.anchor(Anchor::Screen);
+        let bullet = load_frame("../test-assets/bullet.png");
+        hud.add_child(Sprite::child("icon", point2(10, 20), Some(bullet)));
+        root.add_child(hud);
+        let mut screen = RgbaImage::from_pixel(DOT_WIDTH as u32, DOT_HEIGHT as u32, white);
+        root.draw(&mut screen, &camera);
+        // chara1's upper left dot is (4, 2), drawn at (110 - 100 + 4, 60 - 50 + 2)
+        assert_ne!(*screen.get_pixel(14, 12), white);
+        assert_eq!(*screen.get_pixel(110 + 4, 60 + 2), white);
+        // bullet's dot is (7, 8), and the HUD doesn't move with the camera
+        assert_ne!(*screen.get_pixel(210 + 7, 120 + 8), white);
+        assert_eq!(*screen.get_pixel(110 + 7, 70 + 8), white);
+    }
+}
@@ -130,19 +130,19 @@ impl<T> ScheduleType<T> {
             ScheduleType::Iter { next: s, .. } => s,
         }
     }
-    fn once(start: Clock) -> ScheduleType<T> {
+    pub(crate) fn once(start: Clock) -> ScheduleType<T> {
         ScheduleType::Once(start)
     }
-    fn span(start: Clock, span: Clock) -> ScheduleType<T> {
+    pub(crate) fn span(start: Clock, span: Clock) -> ScheduleType<T> {
         ScheduleType::Span(Span::new(start, start + span - 1))
     }
-    fn repeated_once(start: Clock, span: Clock) -> ScheduleType<T> {
+    pub(crate) fn repeated_once(start: Clock, span: Clock) -> ScheduleType<T> {
         ScheduleType::RepeatedOnce {
             span: span,
             next: start,
         }
     }
-    fn repeated_span(start: Clock, span: Clock, exec_span: Clock) -> ScheduleType<T> {
+    pub(crate) fn repeated_span(start: Clock, span: Clock, exec_span: Clock) -> ScheduleType<T> {
         let next = Span::new(start, start + exec_span - 1);
         ScheduleType::RepeatedSpan {
             span: span,
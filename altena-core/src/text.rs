@@ -0,0 +1,576 @@
+//! text layout(line breaking and alignment), shared by all text rendering
+
+use euclid::{point2, size2};
+
+use std::ops::Range;
+
+use font::{FontError, FontSetting};
+use frame::dottypes::*;
+use markup::{self, RichText};
+
+/// Alignment of lines(in vertical text, Left is top and Right is bottom)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+impl Default for Align {
+    fn default() -> Align {
+        Align::Left
+    }
+}
+
+/// Vertical metrics of a font in dots
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LineMetrics {
+    /// distance from the top of the line to the baseline
+    pub ascent: f32,
+    /// distance from the baseline to the bottom of the line(usually negative)
+    pub descent: f32,
+    pub line_gap: f32,
+}
+
+impl LineMetrics {
+    pub fn height(&self) -> f32 {
+        self.ascent - self.descent + self.line_gap
+    }
+}
+
+/// Metrics needed for text layout
+pub trait FontMetrics {
+    /// Horizontal advance of `c`, or None if the font doesn't have it
+    fn advance(&self, c: char, scale: f32) -> Option<f32>;
+    fn kerning(&self, a: char, b: char, scale: f32) -> f32;
+    fn line_metrics(&self, scale: f32) -> LineMetrics;
+}
+
+/// Glyph placed by layout
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlacedGlyph {
+    pub c: char,
+    /// index of the character in the source string(in chars)
+    pub index: usize,
+    /// pen position(left end of the baseline)
+    pub x: f32,
+    pub y: f32,
+    /// index of the line(column in vertical text)
+    pub line: usize,
+    /// rotated by 90 degrees clockwise in its em square(for vertical text)
+    pub rotated: bool,
+    /// space the character takes(advance x em square)
+    pub cell: DotRect,
+}
+
+fn cell_rect(x: f32, y: f32, w: f32, h: f32) -> DotRect {
+    let origin = point2(x.round() as i16, y.round() as i16);
+    DotRect::new(origin, size2(w.round() as i16, h.round() as i16))
+}
+
+/// Result of text layout
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextLayout {
+    pub glyphs: Vec<PlacedGlyph>,
+    /// bounding rect of all lines
+    pub bbox: DotRect,
+    pub lines: usize,
+    /// (index in the source, empty cell at the end of the line) of characters which
+    /// aren't drawn(spaces at line ends, '\r' and '\n')
+    pub hidden: Vec<(usize, DotRect)>,
+}
+
+/// Size and character positions of text, exactly as drawn by TextDraw
+#[derive(Clone, Debug, PartialEq)]
+pub struct Measurement {
+    /// bounding rect of all lines
+    pub bbox: DotRect,
+    pub lines: usize,
+    /// (index of the character in the source, space it takes) for every character,
+    /// in the source order. Characters which aren't drawn have empty cells at the end
+    /// of their line.
+    pub chars: Vec<(usize, DotRect)>,
+}
+
+impl Measurement {
+    fn new(layout: &TextLayout, bbox: DotRect) -> Measurement {
+        let mut chars: Vec<_> = layout
+            .glyphs
+            .iter()
+            .map(|g| (g.index, g.cell))
+            .chain(layout.hidden.iter().cloned())
+            .collect();
+        chars.sort_by_key(|&(i, _)| i);
+        Measurement {
+            bbox: bbox,
+            lines: layout.lines,
+            chars: chars,
+        }
+    }
+    pub fn width(&self) -> i16 {
+        self.bbox.size.width
+    }
+    pub fn height(&self) -> i16 {
+        self.bbox.size.height
+    }
+}
+
+/// Text measurement without drawing, sharing the layout with drawing
+pub trait TextMeasure {
+    fn measure(&self, s: &str, setting: &FontSetting) -> Result<Measurement, FontError>;
+    /// Measure rich text including ruby. `chars` are of `RichText::plain`.
+    fn measure_rich(
+        &self,
+        text: &RichText,
+        setting: &FontSetting,
+    ) -> Result<Measurement, FontError>;
+}
+
+impl<F: FontMetrics + ?Sized> TextMeasure for F {
+    fn measure(&self, s: &str, setting: &FontSetting) -> Result<Measurement, FontError> {
+        let layout = layout(self, s, setting)?;
+        Ok(Measurement::new(&layout, setting.effects.ink_rect(layout.bbox)))
+    }
+    fn measure_rich(
+        &self,
+        text: &RichText,
+        setting: &FontSetting,
+    ) -> Result<Measurement, FontError> {
+        let rich = markup::layout_rich(self, text, setting, text.len())?;
+        Ok(Measurement::new(&rich.plain, rich.bbox))
+    }
+}
+
+/// characters which must not start a line(kinsoku)
+const NO_START: &str = "、。，．,.・：:；;？?！!ー～…‥）)］]｝}」』】〉》〕’”\
+                        ぁぃぅぇぉっゃゅょゎァィゥェォッャュョヮヵヶ々ゝゞヽヾ";
+/// characters which must not end a line(kinsoku)
+const NO_END: &str = "（(［[｛{「『【〈《〔‘“";
+
+/// Whether the line can be broken between `prev` and `next`
+fn can_break_between(prev: char, next: char) -> bool {
+    if NO_START.contains(next) || NO_END.contains(prev) {
+        return false;
+    }
+    // don't break inside latin words
+    !(prev.is_ascii_alphanumeric() && next.is_ascii_alphanumeric())
+}
+
+/// Split a paragraph into lines which fit in `max_width`.
+/// `x[i]` is the pen position of `chars[i]` from the paragraph start.
+fn break_lines(
+    chars: &[char],
+    x: &[f32],
+    adv: &[f32],
+    max_width: Option<f32>,
+) -> Vec<Range<usize>> {
+    let n = chars.len();
+    let max_width = match max_width {
+        Some(w) => w,
+        None => return vec![0..n],
+    };
+    let mut lines = vec![];
+    let (mut start, mut i) = (0, 0);
+    while i < n {
+        let right = x[i] - x[start] + adv[i];
+        if right > max_width && i > start && chars[i] != ' ' {
+            // break at the last opportunity, or force it if there is none
+            let b = (start + 1..=i)
+                .rev()
+                .find(|&b| can_break_between(chars[b - 1], chars[b]))
+                .unwrap_or(i);
+            lines.push(start..b);
+            start = b;
+            while start < n && chars[start] == ' ' {
+                start += 1;
+            }
+            i = start;
+            continue;
+        }
+        i += 1;
+    }
+    lines.push(start..n);
+    lines
+}
+
+/// Line with (char, index in the source, position from the line start, advance), its
+/// length, and indices of characters in the line which aren't drawn(spaces at the end,
+/// '\r' and '\n')
+type Line = (Vec<(char, usize, f32, f32)>, f32, Vec<usize>);
+
+/// Split `s` into lines no longer than `max_len`.
+/// `advance` and `kerning` give lengths along the line.
+fn split_lines(
+    s: &str,
+    advance: &dyn Fn(char) -> Result<f32, FontError>,
+    kerning: &dyn Fn(char, char) -> f32,
+    max_len: Option<f32>,
+) -> Result<Vec<Line>, FontError> {
+    let total = s.chars().count();
+    let mut lines = vec![];
+    let mut base = 0;
+    for para in s.split('\n') {
+        let indexed: Vec<(usize, char)> = para.chars()
+            .enumerate()
+            .filter(|&(_, c)| c != '\r')
+            .map(|(i, c)| (base + i, c))
+            .collect();
+        let para_start = base;
+        base += para.chars().count() + 1;
+        // the end of the paragraph including '\n'
+        let para_end = base.min(total);
+        let chars: Vec<char> = indexed.iter().map(|&(_, c)| c).collect();
+        let adv = chars
+            .iter()
+            .map(|&c| advance(c))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut x = Vec::with_capacity(chars.len());
+        let mut pen = 0.0;
+        for i in 0..chars.len() {
+            if i > 0 {
+                pen += adv[i - 1] + kerning(chars[i - 1], chars[i]);
+            }
+            x.push(pen);
+        }
+        let ranges = break_lines(&chars, &x, &adv, max_len);
+        // index in the source where each line starts
+        let starts: Vec<usize> = ranges
+            .iter()
+            .enumerate()
+            .map(|(k, r)| match indexed.get(r.start) {
+                _ if k == 0 => para_start,
+                Some(&(i, _)) => i,
+                None => para_end,
+            })
+            .collect();
+        for (k, range) in ranges.into_iter().enumerate() {
+            let start = range.start;
+            // trailing spaces don't count
+            let end = range
+                .rev()
+                .find(|&i| chars[i] != ' ')
+                .map_or(start, |i| i + 1);
+            let glyphs: Vec<_> = (start..end)
+                .map(|i| (chars[i], indexed[i].0, x[i] - x[start], adv[i]))
+                .collect();
+            let len = glyphs.last().map_or(0.0, |&(_, _, x, adv)| x + adv);
+            let next = starts.get(k + 1).cloned().unwrap_or(para_end);
+            let hidden = (starts[k]..next)
+                .filter(|&i| glyphs.iter().all(|g| g.1 != i))
+                .collect();
+            lines.push((glyphs, len, hidden));
+        }
+    }
+    Ok(lines)
+}
+
+/// Offset of a line in the box of `box_len`
+fn align_offset(align: Align, box_len: f32, len: f32) -> f32 {
+    match align {
+        Align::Left => 0.0,
+        Align::Center => ((box_len - len) / 2.0).floor(),
+        Align::Right => box_len - len,
+    }
+}
+
+/// Lay out `s` with `setting`. Each '\n' starts a new line.
+pub(crate) fn layout<F: FontMetrics + ?Sized>(
+    font: &F,
+    s: &str,
+    setting: &FontSetting,
+) -> Result<TextLayout, FontError> {
+    if setting.vertical {
+        return layout_vertical(font, s, setting);
+    }
+    let scale = f32::from(setting.scale_u);
+    let metrics = font.line_metrics(scale);
+    let line_height = metrics.height();
+    let em = metrics.ascent - metrics.descent;
+    let max_width = setting.max_width.map(|w| w as f32);
+    let advance = |c| font.advance(c, scale).ok_or(FontError::NoFont(c));
+    let kerning = |a, b| font.kerning(a, b, scale);
+    let lines = split_lines(s, &advance, &kerning, max_width)?;
+    let widest = lines.iter().fold(0.0f32, |w, l| w.max(l.1));
+    let box_width = max_width.unwrap_or(widest);
+    let (sx, sy) = (setting.start.x, setting.start.y);
+    let (mut min_x, mut max_x) = (f32::max_value(), f32::min_value());
+    let (mut glyphs, mut hidden) = (vec![], vec![]);
+    for (i, (line, width, rest)) in lines.iter().enumerate() {
+        let offset = align_offset(setting.align, box_width, *width);
+        min_x = min_x.min(offset);
+        max_x = max_x.max(offset + width);
+        let top = sy + i as f32 * line_height;
+        let baseline = top + metrics.ascent;
+        glyphs.extend(line.iter().map(|&(c, index, x, adv)| {
+            let x = sx + offset + x;
+            PlacedGlyph {
+                c: c,
+                index: index,
+                x: x.round(),
+                y: baseline.round(),
+                line: i,
+                rotated: false,
+                cell: cell_rect(x, top, adv, em),
+            }
+        }));
+        let end = sx + offset + width;
+        hidden.extend(rest.iter().map(|&index| (index, cell_rect(end, top, 0.0, em))));
+    }
+    let height = (lines.len() as f32 * line_height).ceil();
+    let origin = point2((sx + min_x).round() as i16, sy.round() as i16);
+    let bbox = DotRect::new(origin, size2((max_x - min_x).ceil() as i16, height as i16));
+    Ok(TextLayout {
+        glyphs: glyphs,
+        bbox: bbox,
+        lines: lines.len(),
+        hidden: hidden,
+    })
+}
+
+/// Vertical forms of punctuation and brackets, used if the font has them
+const VERTICAL_FORMS: &[(char, char)] = &[
+    ('、', '︑'),
+    ('。', '︒'),
+    ('，', '︐'),
+    ('：', '︓'),
+    ('；', '︔'),
+    ('！', '︕'),
+    ('？', '︖'),
+    ('…', '︙'),
+    ('‥', '︰'),
+    ('（', '︵'),
+    ('）', '︶'),
+    ('｛', '︷'),
+    ('｝', '︸'),
+    ('〔', '︹'),
+    ('〕', '︺'),
+    ('【', '︻'),
+    ('】', '︼'),
+    ('《', '︽'),
+    ('》', '︾'),
+    ('〈', '︿'),
+    ('〉', '﹀'),
+    ('「', '﹁'),
+    ('」', '﹂'),
+    ('『', '﹃'),
+    ('』', '﹄'),
+];
+/// characters rotated by 90 degrees in vertical text(if there is no vertical form)
+const ROTATED: &str = "ー～—―－‐…‥（）｛｝〔〕【】《》〈〉「」『』()[]{}<>-=~";
+/// characters moved to the upper right in vertical text(if there is no vertical form)
+const UPPER_RIGHT: &str = "、。，．";
+
+/// (character to draw, rotated or not, offset ratio to em) of `c` in vertical text
+fn vertical_form<F: FontMetrics + ?Sized>(font: &F, c: char, scale: f32) -> (char, bool, f32) {
+    let form = VERTICAL_FORMS.iter().find(|&&(h, _)| h == c).map(|&(_, v)| v);
+    match form {
+        Some(v) if font.advance(v, scale).is_some() => (v, false, 0.0),
+        _ if ROTATED.contains(c) => (c, true, 0.0),
+        _ if UPPER_RIGHT.contains(c) => (c, false, 0.6),
+        _ => (c, false, 0.0),
+    }
+}
+
+/// Vertical(tategaki) layout: glyphs go top to bottom, and columns go right to
+/// left. Columns are wrapped at `setting.max_height`.
+fn layout_vertical<F: FontMetrics + ?Sized>(
+    font: &F,
+    s: &str,
+    setting: &FontSetting,
+) -> Result<TextLayout, FontError> {
+    let scale = f32::from(setting.scale_u);
+    let metrics = font.line_metrics(scale);
+    let em = metrics.ascent - metrics.descent;
+    let column_width = metrics.height();
+    let max_height = setting.max_height.map(|h| h as f32);
+    // every character takes a square of em
+    let advance = |c| font.advance(c, scale).map(|_| em).ok_or(FontError::NoFont(c));
+    let lines = split_lines(s, &advance, &|_, _| 0.0, max_height)?;
+    let longest = lines.iter().fold(0.0f32, |h, l| h.max(l.1));
+    let box_height = max_height.unwrap_or(longest);
+    let (sx, sy) = (setting.start.x, setting.start.y);
+    let (mut min_y, mut max_y) = (f32::max_value(), f32::min_value());
+    let (mut glyphs, mut hidden) = (vec![], vec![]);
+    let columns = lines.len();
+    for (i, (line, height, rest)) in lines.iter().enumerate() {
+        let offset = align_offset(setting.align, box_height, *height);
+        min_y = min_y.min(offset);
+        max_y = max_y.max(offset + height);
+        let left = sx + (columns - 1 - i) as f32 * column_width;
+        for &(c, index, y, _) in line {
+            let top = sy + offset + y;
+            let (c, rotated, shift) = vertical_form(font, c, scale);
+            let x = if rotated {
+                left
+            } else {
+                let adv = font.advance(c, scale).unwrap_or(em);
+                left + (em - adv) / 2.0 + shift * em
+            };
+            let baseline = top + metrics.ascent - shift * em;
+            glyphs.push(PlacedGlyph {
+                c: c,
+                index: index,
+                x: x.round(),
+                y: baseline.round(),
+                line: i,
+                rotated: rotated,
+                cell: cell_rect(left, top, em, em),
+            });
+        }
+        let end = sy + offset + height;
+        hidden.extend(rest.iter().map(|&index| (index, cell_rect(left, end, em, 0.0))));
+    }
+    let width = (columns as f32 * column_width).ceil();
+    let origin = point2(sx.round() as i16, (sy + min_y).round() as i16);
+    let bbox = DotRect::new(origin, size2(width as i16, (max_y - min_y).ceil() as i16));
+    Ok(TextLayout {
+        glyphs: glyphs,
+        bbox: bbox,
+        lines: columns,
+        hidden: hidden,
+    })
+}
+
+#[cfg(test)]
+mod text_test {
+    use super::*;
+    use euclid::rect;
+    use tile::Color;
+    /// every glyph is 8 dots wide, spaces are 4 dots
+    struct Mono;
+    impl FontMetrics for Mono {
+        fn advance(&self, c: char, _scale: f32) -> Option<f32> {
+            match c {
+                ' ' => Some(4.0),
+                '□' | '︒' => None,
+                _ => Some(8.0),
+            }
+        }
+        fn kerning(&self, _a: char, _b: char, _scale: f32) -> f32 {
+            0.0
+        }
+        fn line_metrics(&self, _scale: f32) -> LineMetrics {
+            LineMetrics {
+                ascent: 7.0,
+                descent: -1.0,
+                line_gap: 2.0,
+            }
+        }
+    }
+    fn lines_of(l: &TextLayout) -> Vec<String> {
+        let mut res = vec![String::new(); l.lines];
+        l.glyphs.iter().for_each(|g| res[g.line].push(g.c));
+        res
+    }
+    #[test]
+    fn newline() {
+        let mut setting = FontSetting::new();
+        setting.start((10.0, 20.0));
+        let l = layout(&Mono, "ab\nc", &setting).unwrap();
+        assert_eq!(lines_of(&l), vec!["ab", "c"]);
+        assert_eq!((l.glyphs[2].x, l.glyphs[2].y), (10.0, 37.0));
+        assert_eq!(l.bbox, rect(10, 20, 16, 20));
+        assert_eq!(l.glyphs[2].index, 3);
+    }
+    #[test]
+    fn wrap_words() {
+        let mut setting = FontSetting::new();
+        setting.max_width(40);
+        let l = layout(&Mono, "ab cd efgh", &setting).unwrap();
+        assert_eq!(lines_of(&l), vec!["ab cd", "efgh"]);
+        assert_eq!(l.bbox.size.width, 36);
+        let l = layout(&Mono, "abcdefgh", &setting).unwrap();
+        assert_eq!(lines_of(&l), vec!["abcde", "fgh"]);
+    }
+    #[test]
+    fn kinsoku() {
+        let mut setting = FontSetting::new();
+        setting.max_width(32);
+        let l = layout(&Mono, "あいうえ。おか", &setting).unwrap();
+        assert_eq!(lines_of(&l), vec!["あいう", "え。おか"]);
+        let l = layout(&Mono, "あいう「えお", &setting).unwrap();
+        assert_eq!(lines_of(&l), vec!["あいう", "「えお"]);
+        setting.max_width(24);
+        let l = layout(&Mono, "あい「う", &setting).unwrap();
+        assert_eq!(lines_of(&l), vec!["あい", "「う"]);
+    }
+    #[test]
+    fn align() {
+        let mut setting = FontSetting::new();
+        setting.max_width(40).align(Align::Right);
+        let l = layout(&Mono, "ab\nabcd", &setting).unwrap();
+        assert_eq!((l.glyphs[0].x, l.glyphs[2].x), (24.0, 8.0));
+        assert_eq!(l.bbox, rect(8, 0, 32, 20));
+        setting.align(Align::Center);
+        let l = layout(&Mono, "ab", &setting).unwrap();
+        assert_eq!(l.glyphs[0].x, 12.0);
+    }
+    #[test]
+    fn vertical() {
+        let mut setting = FontSetting::new();
+        setting.vertical(true).start((10.0, 20.0));
+        let l = layout(&Mono, "あい\nう", &setting).unwrap();
+        // em is 8 dots and columns are 10 dots wide
+        assert_eq!(lines_of(&l), vec!["あい", "う"]);
+        assert_eq!((l.glyphs[0].x, l.glyphs[0].y), (20.0, 27.0));
+        assert_eq!((l.glyphs[1].x, l.glyphs[1].y), (20.0, 35.0));
+        assert_eq!((l.glyphs[2].x, l.glyphs[2].y), (10.0, 27.0));
+        assert_eq!(l.bbox, rect(10, 20, 20, 16));
+    }
+    #[test]
+    fn vertical_forms() {
+        let mut setting = FontSetting::new();
+        setting.vertical(true).max_height(24);
+        let l = layout(&Mono, "あ、。ーい", &setting).unwrap();
+        let chars: Vec<_> = l.glyphs.iter().map(|g| (g.c, g.rotated)).collect();
+        let expected = vec![
+            ('あ', false),
+            ('︑', false),
+            ('。', false),
+            ('ー', true),
+            ('い', false),
+        ];
+        assert_eq!(chars, expected);
+        // Mono doesn't have the vertical form of '。', so it's moved to the upper right
+        assert!(l.glyphs[2].x > l.glyphs[0].x);
+        assert_eq!(l.lines, 2);
+    }
+    #[test]
+    fn measure() {
+        let mut setting = FontSetting::new();
+        setting.start((2.0, 4.0));
+        let m = Mono.measure("ab\nc d", &setting).unwrap();
+        assert_eq!((m.width(), m.height(), m.lines), (20, 20, 2));
+        assert_eq!(m.chars[0], (0, rect(2, 4, 8, 8)));
+        assert_eq!(m.chars[2], (2, rect(18, 4, 0, 8)));
+        assert_eq!(m.chars[4], (4, rect(10, 14, 4, 8)));
+        let l = layout(&Mono, "ab\nc d", &setting).unwrap();
+        assert_eq!(m.bbox, l.bbox);
+        // spaces at the wrap, '\r' and '\n' have empty cells at the end of their line
+        setting.max_width(20);
+        let m = Mono.measure("ab  cd\r\ne", &setting).unwrap();
+        let indices: Vec<_> = m.chars.iter().map(|c| c.0).collect();
+        assert_eq!(indices, (0..9).collect::<Vec<_>>());
+        assert_eq!(m.chars[3], (3, rect(18, 4, 0, 8)));
+        assert_eq!(m.chars[6], (6, rect(18, 14, 0, 8)));
+        assert_eq!(m.chars[7], (7, rect(18, 14, 0, 8)));
+        assert_eq!(m.chars[8], (8, rect(2, 24, 8, 8)));
+        // effects are included in the bbox
+        setting.outline(Color::white(), 1).shadow((2, 2), Color::black());
+        let m = Mono.measure("ab", &setting).unwrap();
+        assert_eq!(m.bbox, rect(1, 3, 20, 14));
+        setting = FontSetting::new();
+        setting.start((2.0, 4.0)).vertical(true);
+        let m = Mono.measure("ab\nc", &setting).unwrap();
+        assert_eq!(m.chars[2], (2, rect(12, 20, 8, 0)));
+        assert_eq!(m.chars[3], (3, rect(2, 4, 8, 8)));
+    }
+    #[test]
+    fn missing_glyph() {
+        let setting = FontSetting::new();
+        match layout(&Mono, "a□", &setting) {
+            Err(FontError::NoFont('□')) => {}
+            r => panic!("{:?}", r),
+        }
+    }
+}
@@ -13,7 +13,7 @@ pub mod tiletypes {
     use euclid::TypedPoint2D;
     use rect_iter::RectRange;
     pub struct TileSpace;
-    pub type TilePoint = TypedPoint2D<u8, TileSpace>;
+    pub type TilePoint = TypedPoint2D<u16, TileSpace>;
     pub const TILE_SIZE: usize = 16;
     /// RectIter for tile
     pub fn tile_rect() -> RectRange<usize> {
@@ -269,7 +269,7 @@ impl Alpha {
     fn inv(self) -> Alpha {
         Alpha(Self::MAX_VALUE - self.0)
     }
-    fn is_trans(self) -> bool {
+    pub fn is_trans(self) -> bool {
         self.0 == 0
     }
     fn blend(self, orig: u8, new: u8) -> u8 {
@@ -467,6 +467,47 @@ impl Tile {
             inner: [d; TILE_SIZE * TILE_SIZE],
         }
     }
+    /// Draw the tile into `buf` with its upper left corner at `(x, y)`.
+    /// Transparent dots are skipped, and dots out of `buf` are clipped.
+    pub fn draw<B, I>(&self, buf: &mut B, x: i16, y: i16, alpha: Alpha)
+    where
+        B: GetMut2D<Item = I>,
+        I: Blend,
+    {
+        blit_tile(buf, x, y, alpha, |(tx, ty), _| self.inner[ty * TILE_SIZE + tx]);
+    }
+}
+
+/// Blend a tile into `buf` with its upper left corner at `(x, y)`.
+/// `color_at` returns the color of (dot in the tile, dot in `buf`), or None if transparent.
+pub(crate) fn blit_tile<B, I, F>(buf: &mut B, x: i16, y: i16, alpha: Alpha, color_at: F)
+where
+    B: GetMut2D<Item = I>,
+    I: Blend,
+    F: Fn((usize, usize), (i16, i16)) -> Option<Color>,
+{
+    // computed in i32, since tiles near the edges of DotSpace overflow i16
+    let (x, y, len) = (i32::from(x), i32::from(y), TILE_SIZE as i32);
+    // the whole tile is out of buf
+    if alpha.is_trans() || x + len <= 0 || y + len <= 0 {
+        return;
+    }
+    let max = i32::from(i16::max_value());
+    for (tx, ty) in tile_rect() {
+        let (px, py) = (x + tx as i32, y + ty as i32);
+        // no buffer has dots beyond i16
+        if px > max || py > max {
+            continue;
+        }
+        let p = (px as i16, py as i16);
+        let color = match color_at((tx, ty), p) {
+            Some(c) => c,
+            None => continue,
+        };
+        if let Some(b) = buf.get_mut_xy(p.0, p.1) {
+            b.blend(color, alpha);
+        }
+    }
 }
 
 impl Get2D for Tile {
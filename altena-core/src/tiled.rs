@@ -0,0 +1,724 @@
+//! importer for Tiled(https://www.mapeditor.org/) maps
+
+use base64;
+use euclid::point2;
+use image::{self, ImageError, RgbaImage};
+use serde_json::{self, Value};
+use tuple_map::TupleMap2;
+use xml::reader::{EventReader, XmlEvent};
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+
+use frame::dottypes::*;
+use tile::tiletypes::*;
+use tilemap::{TileId, TileLayer, TileMap, TilemapError, Tileset, MAX_LAYER_LEN};
+
+/// Tiled stores flipping flags in the upper bits of gid
+const FLIP_MASK: u32 = 0xe000_0000;
+
+#[derive(Debug)]
+pub enum TiledError {
+    Io(io::Error),
+    Image(ImageError),
+    Json(serde_json::Error),
+    Xml(String),
+    /// (width, height) of tiles other than 16×16
+    TileSize(u32, u32),
+    /// encoding of layer data other than csv/base64
+    Encoding(String),
+    /// compressed layer data(zlib, gzip, zstd)
+    Compression(String),
+    /// features we don't handle, like infinite maps or external tilesets
+    Unsupported(&'static str),
+    /// required attribute is missing or invalid
+    Invalid(String),
+    /// the map is too large for altena's tilemap
+    Tilemap(TilemapError),
+}
+
+impl Error for TiledError {
+    fn description(&self) -> &str {
+        "Tiled Error"
+    }
+}
+
+impl fmt::Display for TiledError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            TiledError::Io(e) => write!(f, "Io: {}", e),
+            TiledError::Image(e) => write!(f, "Image: {}", e),
+            TiledError::Json(e) => write!(f, "Json: {}", e),
+            TiledError::Xml(s) => write!(f, "Xml: {}", s),
+            TiledError::TileSize(w, h) => write!(
+                f,
+                "TileSize: {}×{} is not supported, use {}×{}",
+                w, h, TILE_SIZE, TILE_SIZE
+            ),
+            TiledError::Encoding(s) => write!(f, "Encoding: {} is not supported", s),
+            TiledError::Compression(s) => write!(f, "Compression: {} is not supported", s),
+            TiledError::Unsupported(s) => write!(f, "Unsupported: {}", s),
+            TiledError::Invalid(s) => write!(f, "Invalid: {}", s),
+            TiledError::Tilemap(e) => write!(f, "Tilemap: {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for TiledError {
+    fn from(e: io::Error) -> TiledError {
+        TiledError::Io(e)
+    }
+}
+
+impl From<ImageError> for TiledError {
+    fn from(e: ImageError) -> TiledError {
+        TiledError::Image(e)
+    }
+}
+
+impl From<TilemapError> for TiledError {
+    fn from(e: TilemapError) -> TiledError {
+        TiledError::Tilemap(e)
+    }
+}
+
+impl From<serde_json::Error> for TiledError {
+    fn from(e: serde_json::Error) -> TiledError {
+        TiledError::Json(e)
+    }
+}
+
+/// Named point placed in object layers
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpawnPoint {
+    pub name: String,
+    /// `type`(or `class` in newer Tiled) of the object
+    pub kind: String,
+    /// name of the object layer
+    pub layer: String,
+    pub point: DotPoint,
+    pub properties: HashMap<String, String>,
+}
+
+/// Result of importing
+#[derive(Debug)]
+pub struct TiledMap {
+    pub map: TileMap,
+    pub spawns: Vec<SpawnPoint>,
+}
+
+/// Load a map. The format is decided by the extension(.json or .tmx).
+pub fn load(path: &Path) -> Result<TiledMap, TiledError> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => load_json(path),
+        Some("tmx") => load_tmx(path),
+        _ => Err(TiledError::Unsupported("map file other than .json or .tmx")),
+    }
+}
+
+pub fn load_json(path: &Path) -> Result<TiledMap, TiledError> {
+    let f = BufReader::new(File::open(path)?);
+    let value: Value = serde_json::from_reader(f)?;
+    let raw = RawMap::from_json(&value)?;
+    raw.build(path.parent().unwrap_or_else(|| Path::new(".")))
+}
+
+pub fn load_tmx(path: &Path) -> Result<TiledMap, TiledError> {
+    let f = BufReader::new(File::open(path)?);
+    let root = Element::parse(f)?;
+    let raw = RawMap::from_tmx(&root)?;
+    raw.build(path.parent().unwrap_or_else(|| Path::new(".")))
+}
+
+/// Map data common to json and tmx
+struct RawMap {
+    width: usize,
+    height: usize,
+    tilesets: Vec<RawTileset>,
+    layers: Vec<RawLayer>,
+}
+
+struct RawTileset {
+    first_gid: u32,
+    image: String,
+    /// tiles in a row of the image
+    columns: u32,
+    tile_count: u32,
+}
+
+enum RawLayer {
+    Tile {
+        name: String,
+        data: Vec<u32>,
+        visible: bool,
+        parallax: (f32, f32),
+    },
+    Object {
+        name: String,
+        objects: Vec<RawObject>,
+    },
+}
+
+struct RawObject {
+    name: String,
+    kind: String,
+    x: f64,
+    y: f64,
+    properties: HashMap<String, String>,
+}
+
+fn check_tile_size(w: u32, h: u32) -> Result<(), TiledError> {
+    if w as usize == TILE_SIZE && h as usize == TILE_SIZE {
+        Ok(())
+    } else {
+        Err(TiledError::TileSize(w, h))
+    }
+}
+
+fn to_u32(u: u64, name: &str) -> Result<u32, TiledError> {
+    if u <= u64::from(u32::max_value()) {
+        Ok(u as u32)
+    } else {
+        Err(TiledError::Invalid(format!("{} {} is too large", name, u)))
+    }
+}
+
+/// Convert an object position to DotSpace
+fn to_dot(f: f64, name: &str) -> Result<i16, TiledError> {
+    let f = f.round();
+    if f64::from(i16::min_value()) <= f && f <= f64::from(i16::max_value()) {
+        Ok(f as i16)
+    } else {
+        Err(TiledError::Invalid(format!("{} {} is out of DotSpace", name, f)))
+    }
+}
+
+/// Cut tiles of `raw` out of its image, in the order of Tiled's tile ids.
+/// Tiled ignores the partial tiles at the right and bottom edges, so we do too.
+fn load_tileset(raw: &RawTileset, base_dir: &Path) -> Result<Tileset, TiledError> {
+    let img = image::open(base_dir.join(&raw.image))?.to_rgba();
+    if raw.columns == 0 || raw.tile_count == 0 {
+        return Err(TiledError::Invalid(format!("empty tileset {}", raw.image)));
+    }
+    let (columns, count) = (raw.columns, raw.tile_count).map(u64::from);
+    let rows = (count + columns - 1) / columns;
+    let (w, h) = (columns, rows).map(|t| t * TILE_SIZE as u64);
+    if w > u64::from(img.width()) || h > u64::from(img.height()) {
+        return Err(TiledError::Invalid(format!(
+            "{} tiles in {} columns don't fit in {}",
+            raw.tile_count, raw.columns, raw.image
+        )));
+    }
+    let img = RgbaImage::from_fn(w as u32, h as u32, |x, y| *img.get_pixel(x, y));
+    Tileset::from_buf(&img)
+        .ok_or_else(|| TilemapError::TileCount((columns * rows) as usize).into())
+}
+
+fn missing(name: &str) -> TiledError {
+    TiledError::Invalid(format!("{} is missing", name))
+}
+
+impl RawMap {
+    fn build(self, base_dir: &Path) -> Result<TiledMap, TiledError> {
+        if self.width > MAX_LAYER_LEN || self.height > MAX_LAYER_LEN {
+            return Err(TilemapError::LayerSize(self.width, self.height).into());
+        }
+        // merge all tilesets into one altena Tileset
+        let mut tileset = Tileset::new();
+        let mut gid_ranges = vec![];
+        for raw in &self.tilesets {
+            let ts = load_tileset(raw, base_dir)?;
+            let start = u32::from(tileset.append(ts)?);
+            // tiles in the last row after tilecount are never used
+            let end = raw.first_gid
+                .checked_add(raw.tile_count)
+                .ok_or_else(|| TiledError::Invalid(format!("firstgid {}", raw.first_gid)))?;
+            gid_ranges.push((raw.first_gid, end, start));
+        }
+        let to_id = |gid: u32| -> Result<Option<TileId>, TiledError> {
+            if gid & FLIP_MASK != 0 {
+                warn!("flipped tiles are not supported, so the flag of {} is ignored", gid);
+            }
+            let gid = gid & !FLIP_MASK;
+            if gid == 0 {
+                return Ok(None);
+            }
+            gid_ranges
+                .iter()
+                .find(|&&(first, end, _)| first <= gid && gid < end)
+                .map(|&(first, _, start)| Some((gid - first + start) as TileId))
+                .ok_or_else(|| TiledError::Invalid(format!("gid {} is not in tilesets", gid)))
+        };
+        let mut spawns = vec![];
+        let mut layers = vec![];
+        for layer in self.layers {
+            match layer {
+                RawLayer::Tile {
+                    name,
+                    data,
+                    visible,
+                    parallax,
+                } => {
+                    if data.len() != self.width * self.height {
+                        return Err(TiledError::Invalid(format!(
+                            "layer {} has {} tiles, but the map has {}×{}",
+                            name,
+                            data.len(),
+                            self.width,
+                            self.height
+                        )));
+                    }
+                    let mut res = TileLayer::new(&name, self.width, self.height)?;
+                    res.visible(visible).parallax(parallax.0, parallax.1);
+                    for (i, gid) in data.into_iter().enumerate() {
+                        res.set(i % self.width, i / self.width, to_id(gid)?);
+                    }
+                    layers.push(res);
+                }
+                RawLayer::Object { name, objects } => {
+                    for o in objects {
+                        spawns.push(SpawnPoint {
+                            point: point2(to_dot(o.x, "x")?, to_dot(o.y, "y")?),
+                            name: o.name,
+                            kind: o.kind,
+                            layer: name.clone(),
+                            properties: o.properties,
+                        });
+                    }
+                }
+            }
+        }
+        let mut map = TileMap::new(tileset);
+        layers.into_iter().for_each(|l| {
+            map.push_layer(l);
+        });
+        Ok(TiledMap {
+            map: map,
+            spawns: spawns,
+        })
+    }
+
+    fn from_json(v: &Value) -> Result<RawMap, TiledError> {
+        let get_u32 = |v: &Value, key: &str| {
+            let u = v[key].as_u64().ok_or_else(|| missing(key))?;
+            to_u32(u, key)
+        };
+        if v["infinite"].as_bool() == Some(true) {
+            return Err(TiledError::Unsupported("infinite map"));
+        }
+        check_tile_size(get_u32(v, "tilewidth")?, get_u32(v, "tileheight")?)?;
+        let tilesets = v["tilesets"]
+            .as_array()
+            .ok_or_else(|| missing("tilesets"))?
+            .iter()
+            .map(|ts| {
+                if ts.get("source").is_some() {
+                    return Err(TiledError::Unsupported("external tileset"));
+                }
+                check_tile_size(get_u32(ts, "tilewidth")?, get_u32(ts, "tileheight")?)?;
+                let margin = get_u32(ts, "margin").unwrap_or(0);
+                check_spacing(margin, get_u32(ts, "spacing").unwrap_or(0))?;
+                Ok(RawTileset {
+                    first_gid: get_u32(ts, "firstgid")?,
+                    image: ts["image"]
+                        .as_str()
+                        .ok_or_else(|| missing("image"))?
+                        .to_owned(),
+                    columns: get_u32(ts, "columns")?,
+                    tile_count: get_u32(ts, "tilecount")?,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut layers = vec![];
+        for l in v["layers"].as_array().ok_or_else(|| missing("layers"))? {
+            let name = l["name"].as_str().unwrap_or("").to_owned();
+            match l["type"].as_str() {
+                Some("tilelayer") => {
+                    let data = match l["encoding"].as_str() {
+                        None | Some("csv") => l["data"]
+                            .as_array()
+                            .ok_or_else(|| missing("data"))?
+                            .iter()
+                            .map(|g| {
+                                let u = g.as_u64().ok_or_else(|| missing("gid"))?;
+                                to_u32(u, "gid")
+                            })
+                            .collect::<Result<Vec<_>, _>>()?,
+                        Some("base64") => {
+                            let s = l["data"].as_str().ok_or_else(|| missing("data"))?;
+                            decode_base64(s, l["compression"].as_str())?
+                        }
+                        Some(e) => return Err(TiledError::Encoding(e.to_owned())),
+                    };
+                    layers.push(RawLayer::Tile {
+                        name: name,
+                        data: data,
+                        visible: l["visible"].as_bool().unwrap_or(true),
+                        parallax: (
+                            l["parallaxx"].as_f64().unwrap_or(1.0) as f32,
+                            l["parallaxy"].as_f64().unwrap_or(1.0) as f32,
+                        ),
+                    });
+                }
+                Some("objectgroup") => {
+                    let objects = l["objects"]
+                        .as_array()
+                        .ok_or_else(|| missing("objects"))?
+                        .iter()
+                        .map(|o| RawObject {
+                            name: o["name"].as_str().unwrap_or("").to_owned(),
+                            kind: o["type"]
+                                .as_str()
+                                .or_else(|| o["class"].as_str())
+                                .unwrap_or("")
+                                .to_owned(),
+                            x: o["x"].as_f64().unwrap_or(0.0),
+                            y: o["y"].as_f64().unwrap_or(0.0),
+                            properties: json_properties(o),
+                        })
+                        .collect();
+                    layers.push(RawLayer::Object {
+                        name: name,
+                        objects: objects,
+                    });
+                }
+                t => warn!("layer {} of type {:?} is ignored", name, t),
+            }
+        }
+        Ok(RawMap {
+            width: get_u32(v, "width")? as usize,
+            height: get_u32(v, "height")? as usize,
+            tilesets: tilesets,
+            layers: layers,
+        })
+    }
+
+    fn from_tmx(root: &Element) -> Result<RawMap, TiledError> {
+        if root.name != "map" {
+            return Err(TiledError::Xml(format!("root element is {}", root.name)));
+        }
+        if root.attr("infinite") == Some("1") {
+            return Err(TiledError::Unsupported("infinite map"));
+        }
+        check_tile_size(root.parse("tilewidth")?, root.parse("tileheight")?)?;
+        let tilesets = root.children("tileset")
+            .map(|ts| {
+                if ts.attr("source").is_some() {
+                    return Err(TiledError::Unsupported("external tileset"));
+                }
+                check_tile_size(ts.parse("tilewidth")?, ts.parse("tileheight")?)?;
+                check_spacing(ts.parse_or("margin", 0)?, ts.parse_or("spacing", 0)?)?;
+                let image = ts.child("image").ok_or_else(|| missing("image"))?;
+                Ok(RawTileset {
+                    first_gid: ts.parse("firstgid")?,
+                    image: image.attr("source").ok_or_else(|| missing("source"))?.to_owned(),
+                    columns: ts.parse("columns")?,
+                    tile_count: ts.parse("tilecount")?,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut layers = vec![];
+        for l in &root.children {
+            let name = l.attr("name").unwrap_or("").to_owned();
+            match l.name.as_str() {
+                "layer" => {
+                    let data = l.child("data").ok_or_else(|| missing("data"))?;
+                    let gids = match data.attr("encoding") {
+                        None => data.children("tile")
+                            .map(|t| t.parse_or("gid", 0))
+                            .collect::<Result<Vec<_>, _>>()?,
+                        Some("csv") => {
+                            if data.child("chunk").is_some() {
+                                return Err(TiledError::Unsupported("infinite map"));
+                            }
+                            data.text
+                                .split(',')
+                                .map(|s| {
+                                    let s = s.trim();
+                                    s.parse::<u32>()
+                                        .map_err(|_| TiledError::Invalid(format!("gid {}", s)))
+                                })
+                                .collect::<Result<Vec<_>, _>>()?
+                        }
+                        Some("base64") => decode_base64(&data.text, data.attr("compression"))?,
+                        Some(e) => return Err(TiledError::Encoding(e.to_owned())),
+                    };
+                    layers.push(RawLayer::Tile {
+                        name: name,
+                        data: gids,
+                        visible: l.attr("visible") != Some("0"),
+                        parallax: (l.parse_or("parallaxx", 1.0)?, l.parse_or("parallaxy", 1.0)?),
+                    });
+                }
+                "objectgroup" => {
+                    let objects = l.children("object")
+                        .map(|o| {
+                            let properties = o.child("properties")
+                                .map(|p| {
+                                    p.children("property")
+                                        .filter_map(|p| {
+                                            let value = p.attr("value").unwrap_or(&p.text);
+                                            Some((p.attr("name")?.to_owned(), value.to_owned()))
+                                        })
+                                        .collect()
+                                })
+                                .unwrap_or_default();
+                            Ok(RawObject {
+                                name: o.attr("name").unwrap_or("").to_owned(),
+                                kind: o.attr("type")
+                                    .or_else(|| o.attr("class"))
+                                    .unwrap_or("")
+                                    .to_owned(),
+                                x: o.parse_or("x", 0.0)?,
+                                y: o.parse_or("y", 0.0)?,
+                                properties: properties,
+                            })
+                        })
+                        .collect::<Result<Vec<_>, TiledError>>()?;
+                    layers.push(RawLayer::Object {
+                        name: name,
+                        objects: objects,
+                    });
+                }
+                "tileset" | "properties" | "editorsettings" => {}
+                t => warn!("layer {} of type {} is ignored", name, t),
+            }
+        }
+        Ok(RawMap {
+            width: root.parse("width")?,
+            height: root.parse("height")?,
+            tilesets: tilesets,
+            layers: layers,
+        })
+    }
+}
+
+fn check_spacing(margin: u32, spacing: u32) -> Result<(), TiledError> {
+    if margin == 0 && spacing == 0 {
+        Ok(())
+    } else {
+        Err(TiledError::Unsupported("tileset with margin or spacing"))
+    }
+}
+
+/// Decode base64 layer data into gids(little endian u32)
+fn decode_base64(s: &str, compression: Option<&str>) -> Result<Vec<u32>, TiledError> {
+    match compression {
+        None | Some("") => {}
+        Some(c) => return Err(TiledError::Compression(c.to_owned())),
+    }
+    let bytes = base64::decode(s.trim())
+        .map_err(|e| TiledError::Invalid(format!("base64 data: {}", e)))?;
+    if bytes.len() % 4 != 0 {
+        return Err(TiledError::Invalid("length of base64 data".to_owned()));
+    }
+    Ok(bytes
+        .chunks(4)
+        .map(|b| (0..4).fold(0u32, |acc, i| acc | u32::from(b[i]) << (8 * i)))
+        .collect())
+}
+
+/// Read custom properties of a json object as strings
+fn json_properties(o: &Value) -> HashMap<String, String> {
+    let props = match o["properties"].as_array() {
+        Some(p) => p,
+        None => return HashMap::new(),
+    };
+    props
+        .iter()
+        .filter_map(|p| {
+            let name = p["name"].as_str()?.to_owned();
+            let value = match &p["value"] {
+                Value::String(s) => s.clone(),
+                v => v.to_string(),
+            };
+            Some((name, value))
+        })
+        .collect()
+}
+
+/// Minimal DOM used to read tmx
+#[derive(Debug, Default)]
+struct Element {
+    name: String,
+    attrs: HashMap<String, String>,
+    children: Vec<Element>,
+    text: String,
+}
+
+impl Element {
+    fn parse<R: io::Read>(r: R) -> Result<Element, TiledError> {
+        let mut stack: Vec<Element> = vec![];
+        for event in EventReader::new(r) {
+            match event.map_err(|e| TiledError::Xml(e.to_string()))? {
+                XmlEvent::StartElement {
+                    name, attributes, ..
+                } => stack.push(Element {
+                    name: name.local_name,
+                    attrs: attributes
+                        .into_iter()
+                        .map(|a| (a.name.local_name, a.value))
+                        .collect(),
+                    ..Element::default()
+                }),
+                XmlEvent::EndElement { .. } => {
+                    let elem = stack.pop().expect("xml-rs ensures tags are balanced");
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(elem),
+                        None => return Ok(elem),
+                    }
+                }
+                XmlEvent::Characters(s) | XmlEvent::CData(s) => {
+                    if let Some(e) = stack.last_mut() {
+                        e.text.push_str(&s);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Err(TiledError::Xml("no root element".to_owned()))
+    }
+    fn attr(&self, key: &str) -> Option<&str> {
+        self.attrs.get(key).map(|s| s.as_str())
+    }
+    fn parse_or<T: ::std::str::FromStr>(&self, key: &str, default: T) -> Result<T, TiledError> {
+        match self.attr(key) {
+            Some(s) => s.parse()
+                .map_err(|_| TiledError::Invalid(format!("{}=\"{}\"", key, s))),
+            None => Ok(default),
+        }
+    }
+    fn parse<T: ::std::str::FromStr>(&self, key: &str) -> Result<T, TiledError> {
+        let s = self.attr(key).ok_or_else(|| missing(key))?;
+        s.parse()
+            .map_err(|_| TiledError::Invalid(format!("{}=\"{}\"", key, s)))
+    }
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|c| c.name == name)
+    }
+    fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> + 'a {
+        self.children.iter().filter(move |c| c.name == name)
+    }
+}
+
+#[cfg(test)]
+mod tiled_test {
+    use super::*;
+    use rect_iter::Get2D;
+    fn assert_same(a: &TiledMap, b: &TiledMap) {
+        assert_eq!(a.spawns, b.spawns);
+        assert_eq!(a.map.layers().len(), b.map.layers().len());
+        for (la, lb) in a.map.layers().iter().zip(b.map.layers()) {
+            assert_eq!(la.name(), lb.name());
+            for y in 0..la.height() {
+                for x in 0..la.width() {
+                    assert_eq!(la.get(x, y), lb.get(x, y));
+                }
+            }
+        }
+    }
+    #[test]
+    fn load_json_map() {
+        let res = load(Path::new("../test-assets/tiled/map.json")).unwrap();
+        assert_eq!(res.map.tileset().len(), 4);
+        let ground = res.map.layer_by_name("ground").unwrap();
+        assert_eq!((ground.width(), ground.height()), (4, 3));
+        assert_eq!(ground.get(0, 0), Some(0));
+        assert_eq!(ground.get(3, 2), Some(3));
+        assert_eq!(ground.get(1, 1), None);
+        let bg = res.map.layer_by_name("background").unwrap();
+        assert!(!bg.is_visible());
+        assert_eq!(res.spawns.len(), 1);
+        let player = &res.spawns[0];
+        assert_eq!(player.name, "player");
+        assert_eq!(player.kind, "spawn");
+        assert_eq!(player.point, point2(24, 32));
+        assert_eq!(player.properties["direction"], "left");
+    }
+    #[test]
+    fn load_tmx_map() {
+        let json = load(Path::new("../test-assets/tiled/map.json")).unwrap();
+        let tmx = load(Path::new("../test-assets/tiled/map.tmx")).unwrap();
+        assert_same(&json, &tmx);
+        let tile_j = json.map.tileset().get(2).unwrap();
+        let tile_t = tmx.map.tileset().get(2).unwrap();
+        tile_rect().into_iter().for_each(|p| {
+            assert_eq!(tile_j.get_point(p), tile_t.get_point(p));
+        });
+    }
+    #[test]
+    fn base64_data() {
+        // [1, 2, 0, 3]
+        let gids = decode_base64("AQAAAAIAAAAAAAAAAwAAAA==", None).unwrap();
+        assert_eq!(gids, vec![1, 2, 0, 3]);
+        match decode_base64("AQAAAAIAAAAAAAAAAwAAAA==", Some("zlib")) {
+            Err(TiledError::Compression(ref c)) if c == "zlib" => {}
+            x => panic!("expected compression error, but got {:?}", x),
+        }
+    }
+    #[test]
+    fn tile_size_error() {
+        match check_tile_size(32, 32) {
+            Err(TiledError::TileSize(32, 32)) => {}
+            x => panic!("expected tile size error, but got {:?}", x),
+        }
+    }
+    #[test]
+    fn tileset_columns() {
+        // use only the left column of chara2.png(32×32)
+        let raw = |columns, tile_count| RawMap {
+            width: 2,
+            height: 1,
+            tilesets: vec![RawTileset {
+                first_gid: 1,
+                image: "../chara2.png".to_owned(),
+                columns: columns,
+                tile_count: tile_count,
+            }],
+            layers: vec![RawLayer::Tile {
+                name: "ground".to_owned(),
+                data: vec![1, 2],
+                visible: true,
+                parallax: (1.0, 1.0),
+            }],
+        };
+        let base_dir = Path::new("../test-assets/tiled");
+        let res = raw(1, 2).build(base_dir).unwrap();
+        assert_eq!(res.map.tileset().len(), 2);
+        let ground = res.map.layer_by_name("ground").unwrap();
+        assert_eq!(ground.get(1, 0), Some(1));
+        let img = image::open("../test-assets/chara2.png").unwrap().to_rgba();
+        let all = Tileset::from_buf(&img).unwrap();
+        let (tile, expected) = (res.map.tileset().get(1).unwrap(), all.get(2).unwrap());
+        tile_rect().into_iter().for_each(|p| {
+            assert_eq!(tile.get_point(p), expected.get_point(p));
+        });
+        match raw(3, 3).build(base_dir) {
+            Err(TiledError::Invalid(_)) => {}
+            x => panic!("expected invalid error, but got {:?}", x),
+        }
+        let mut large = raw(1, 2);
+        large.width = MAX_LAYER_LEN + 1;
+        match large.build(base_dir) {
+            Err(TiledError::Tilemap(TilemapError::LayerSize(2049, 1))) => {}
+            x => panic!("expected layer size error, but got {:?}", x),
+        }
+    }
+    #[test]
+    fn overflow_error() {
+        assert_eq!(to_u32(5, "width").unwrap(), 5);
+        match to_u32(1 << 32, "width") {
+            Err(TiledError::Invalid(_)) => {}
+            x => panic!("expected invalid error, but got {:?}", x),
+        }
+        assert_eq!(to_dot(-32768.4, "x").unwrap(), -32768);
+        match to_dot(32767.5, "x") {
+            Err(TiledError::Invalid(_)) => {}
+            x => panic!("expected invalid error, but got {:?}", x),
+        }
+    }
+}
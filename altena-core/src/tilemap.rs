@@ -0,0 +1,632 @@
+//! tilemap built on Tile
+
+use euclid::{point2, size2, vec2};
+use image::RgbaImage;
+use rect_iter::{gen_rect_conv, GetMut2D, RectRange};
+use tuple_map::TupleMap2;
+
+use std::cmp::{max, min};
+use std::error::Error;
+use std::fmt;
+
+use camera::Camera;
+use collision::{LayerMatrix, LayerPairs};
+use frame::dottypes::*;
+use frame::{tile_num, MeshTree};
+use tile::tiletypes::*;
+use tile::{Alpha, AltenaAlpha, Blend, Color, Tile};
+
+/// Index of a tile in Tileset
+pub type TileId = u16;
+
+/// Max width and height of a layer in tiles, so that all dots are in DotSpace
+pub const MAX_LAYER_LEN: usize = (i16::max_value() as usize + 1) / TILE_SIZE;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TilemapError {
+    /// (width, height) of a layer larger than MAX_LAYER_LEN tiles
+    LayerSize(usize, usize),
+    /// number of tiles more than TileId can index
+    TileCount(usize),
+}
+
+impl Error for TilemapError {
+    fn description(&self) -> &str {
+        "Tilemap Error"
+    }
+}
+
+impl fmt::Display for TilemapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            TilemapError::LayerSize(w, h) => write!(
+                f,
+                "LayerSize: {}×{} is larger than {}×{}",
+                w, h, MAX_LAYER_LEN, MAX_LAYER_LEN
+            ),
+            TilemapError::TileCount(n) => write!(f, "TileCount: {} tiles", n),
+        }
+    }
+}
+
+/// All tiles in a tileset must have a TileId
+fn check_tile_count(count: usize) -> Result<(), TilemapError> {
+    if count <= usize::from(TileId::max_value()) + 1 {
+        Ok(())
+    } else {
+        Err(TilemapError::TileCount(count))
+    }
+}
+
+/// Drawing and collision attributes of a tile
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TileAttr {
+    pub alpha: Alpha,
+    /// collision attribute bits(OR of the alpha collision nibble of all dots)
+    pub collision: u8,
+}
+
+/// Contact between an object and tilemap
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TileContact {
+    /// bounding rect of overlapping dots in world coordinates
+    pub rect: DotRect,
+    /// vector which pushes the object out of the tiles.
+    /// Each tile is pushed out in its shallower axis, and the deepest push in each
+    /// axis is taken over all tiles(e.g. (0, -3) for a floor, and (-5, -3) for a
+    /// floor and a wall on the right).
+    pub penetration: DotVector,
+    /// OR of collision attribute bits of tile dots which hit the object
+    pub bits: u8,
+}
+
+impl TileContact {
+    /// Merge contacts with two tiles, keeping the deepest push in each axis
+    fn merge(self, other: TileContact) -> TileContact {
+        let deeper = |a: i16, b: i16| if b.abs() > a.abs() { b } else { a };
+        let (p, q) = (self.penetration, other.penetration);
+        TileContact {
+            rect: self.rect.union(&other.rect),
+            penetration: vec2(deeper(p.x, q.x), deeper(p.y, q.y)),
+            bits: self.bits | other.bits,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct TileEntry {
+    tile: Tile,
+    attr: TileAttr,
+    /// collision mesh, None if the tile has no collision bits
+    mesh: Option<MeshTree>,
+}
+
+/// Set of tiles indexed by TileId
+#[derive(Clone, Debug, Default)]
+pub struct Tileset {
+    tiles: Vec<TileEntry>,
+}
+
+impl Tileset {
+    pub fn new() -> Tileset {
+        Tileset::default()
+    }
+    /// Split an image into 16×16 tiles.
+    /// TileIds are given from left to right, top to bottom.
+    /// Returns None if the image is empty or has more tiles than TileId can index.
+    pub fn from_buf(buf: &RgbaImage) -> Option<Tileset> {
+        let (w, h) = (buf.width(), buf.height()).map(|u| u as usize);
+        check_tile_count(tile_num(w) * tile_num(h)).ok()?;
+        let tile_range = RectRange::zero_start(tile_num(w), tile_num(h))?;
+        let mut res = Tileset::new();
+        // RectRange iterates x first, but we want TileId to be row major
+        for tile_y in tile_range.get_y().clone() {
+            for tile_x in tile_range.get_x().clone() {
+                let (sx, sy) = (tile_x, tile_y).map(|t| TILE_SIZE * t);
+                let (ex, ey) = ((sx, w), (sy, h)).map(|(start, len)| min(start + TILE_SIZE, len));
+                let buf_rect = RectRange::new(sx, sy, ex, ey)?;
+                let tile = gen_rect_conv(buf, Tile::default, buf_rect, tile_rect(), Color::from_rgba)
+                    .ok()?;
+                let mut attr = TileAttr {
+                    alpha: Alpha(0),
+                    collision: 0,
+                };
+                for y in sy..ey {
+                    for x in sx..ex {
+                        let p = buf.get_pixel(x as u32, y as u32);
+                        attr.alpha.max(p.alpha());
+                        attr.collision |= p.collision_bits();
+                    }
+                }
+                let (sx, sy, ex, ey) = (sx as u32, sy as u32, ex as u32, ey as u32);
+                let mesh_range = RectRange::new(sx, sy, ex, ey)?;
+                let mesh = MeshTree::from_tile_buf(buf, mesh_range);
+                res.tiles.push(TileEntry {
+                    tile: tile,
+                    attr: attr,
+                    mesh: mesh,
+                });
+            }
+        }
+        Some(res)
+    }
+    /// Add a tile. If `attr` has collision bits, the whole tile is solid.
+    pub fn push(&mut self, tile: Tile, attr: TileAttr) -> Result<TileId, TilemapError> {
+        check_tile_count(self.tiles.len() + 1)?;
+        let id = self.tiles.len() as TileId;
+        let mesh = if attr.collision != 0 {
+            Some(MeshTree::solid_tile(attr.collision))
+        } else {
+            None
+        };
+        self.tiles.push(TileEntry {
+            tile: tile,
+            attr: attr,
+            mesh: mesh,
+        });
+        Ok(id)
+    }
+    /// Add all tiles in `other`, and return the TileId of its first tile
+    pub fn append(&mut self, other: Tileset) -> Result<TileId, TilemapError> {
+        check_tile_count(self.tiles.len() + other.tiles.len())?;
+        let id = self.tiles.len() as TileId;
+        self.tiles.extend(other.tiles);
+        Ok(id)
+    }
+    pub fn get(&self, id: TileId) -> Option<&Tile> {
+        self.tiles.get(usize::from(id)).map(|t| &t.tile)
+    }
+    pub fn attr(&self, id: TileId) -> Option<TileAttr> {
+        self.tiles.get(usize::from(id)).map(|t| t.attr)
+    }
+    pub fn len(&self) -> usize {
+        self.tiles.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+}
+
+/// A layer of tilemap
+#[derive(Clone, Debug)]
+pub struct TileLayer {
+    name: String,
+    /// width in tiles
+    width: usize,
+    /// height in tiles
+    height: usize,
+    cells: Vec<Option<TileId>>,
+    /// how fast the layer scrolls relative to the camera
+    /// (1.0, 1.0) means it moves with the world, (0.0, 0.0) means it's fixed on the screen
+    parallax: (f32, f32),
+    visible: bool,
+    /// whether the layer is used for collision detection
+    collision: bool,
+}
+
+impl TileLayer {
+    /// Empty layer of `width`×`height` tiles, up to MAX_LAYER_LEN×MAX_LAYER_LEN
+    pub fn new(name: &str, width: usize, height: usize) -> Result<TileLayer, TilemapError> {
+        if width > MAX_LAYER_LEN || height > MAX_LAYER_LEN {
+            return Err(TilemapError::LayerSize(width, height));
+        }
+        Ok(TileLayer {
+            name: name.to_owned(),
+            width: width,
+            height: height,
+            cells: vec![None; width * height],
+            parallax: (1.0, 1.0),
+            visible: true,
+            collision: true,
+        })
+    }
+    pub fn parallax(&mut self, x: f32, y: f32) -> &mut TileLayer {
+        self.parallax = (x, y);
+        self
+    }
+    pub fn visible(&mut self, b: bool) -> &mut TileLayer {
+        self.visible = b;
+        self
+    }
+    pub fn collision(&mut self, b: bool) -> &mut TileLayer {
+        self.collision = b;
+        self
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn height(&self) -> usize {
+        self.height
+    }
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+    pub fn get(&self, x: usize, y: usize) -> Option<TileId> {
+        if self.width <= x || self.height <= y {
+            return None;
+        }
+        self.cells[y * self.width + x]
+    }
+    /// Put a tile to (x, y). Returns false if (x, y) is out of the layer.
+    pub fn set(&mut self, x: usize, y: usize, id: Option<TileId>) -> bool {
+        if self.width <= x || self.height <= y {
+            return false;
+        }
+        self.cells[y * self.width + x] = id;
+        true
+    }
+    /// Upper left corner of the layer's view, considering parallax
+    fn view_origin(&self, camera: &Camera) -> DotPoint {
+        let origin = camera.view_origin();
+        let (x, y) = (
+            (origin.x, self.parallax.0),
+            (origin.y, self.parallax.1),
+        ).map(|(o, p)| (f32::from(o) * p).round() as i16);
+        DotPoint::new(x, y)
+    }
+    /// Range of tiles in `view`(DotSpace), clamped into the layer
+    fn tile_range(&self, view: DotRect) -> Option<RectRange<usize>> {
+        let len = TILE_SIZE as i32;
+        let start = |p: i16| (max(i32::from(p), 0) / len) as usize;
+        let end = |p: i16, lim: usize| min(((max(i32::from(p), 0) + len - 1) / len) as usize, lim);
+        RectRange::new(
+            start(view.min_x()),
+            start(view.min_y()),
+            end(view.max_x(), self.width),
+            end(view.max_y(), self.height),
+        )
+    }
+    fn draw<B, I>(&self, buf: &mut B, tileset: &Tileset, camera: &Camera)
+    where
+        B: GetMut2D<Item = I>,
+        I: Blend,
+    {
+        let origin = self.view_origin(camera);
+        let view = DotRect::new(origin, camera.visible_rect().size);
+        let range = match self.tile_range(view) {
+            Some(r) => r,
+            None => return,
+        };
+        for (x, y) in range {
+            let id = match self.cells[y * self.width + x] {
+                Some(id) => id,
+                None => continue,
+            };
+            let entry = match tileset.tiles.get(usize::from(id)) {
+                Some(t) => t,
+                None => {
+                    warn!("tile id {} is not in tileset", id);
+                    continue;
+                }
+            };
+            // the layer is at most MAX_LAYER_LEN tiles, but the origin can be anywhere
+            let (sx, sy) = ((x, origin.x), (y, origin.y))
+                .map(|(t, o)| (t * TILE_SIZE) as i32 - i32::from(o));
+            if sx > i32::from(i16::max_value()) || sy > i32::from(i16::max_value()) {
+                continue;
+            }
+            entry.tile.draw(buf, sx as i16, sy as i16, entry.attr.alpha);
+        }
+    }
+}
+
+/// Tilemap which consists of a tileset and layers
+#[derive(Clone, Debug)]
+pub struct TileMap {
+    tileset: Tileset,
+    /// layers are drawn from the first to the last
+    layers: Vec<TileLayer>,
+}
+
+impl TileMap {
+    pub fn new(tileset: Tileset) -> TileMap {
+        TileMap {
+            tileset: tileset,
+            layers: vec![],
+        }
+    }
+    pub fn push_layer(&mut self, layer: TileLayer) -> usize {
+        self.layers.push(layer);
+        self.layers.len() - 1
+    }
+    pub fn tileset(&self) -> &Tileset {
+        &self.tileset
+    }
+    pub fn layers(&self) -> &[TileLayer] {
+        &self.layers
+    }
+    pub fn layer_mut(&mut self, id: usize) -> Option<&mut TileLayer> {
+        self.layers.get_mut(id)
+    }
+    pub fn layer_by_name(&self, name: &str) -> Option<&TileLayer> {
+        self.layers.iter().find(|l| l.name == name)
+    }
+    /// Draw visible tiles of visible layers into `buf`(screen coordinates).
+    pub fn draw<B, I>(&self, buf: &mut B, camera: &Camera)
+    where
+        B: GetMut2D<Item = I>,
+        I: Blend,
+    {
+        self.layers
+            .iter()
+            .filter(|l| l.visible)
+            .for_each(|l| l.draw(buf, &self.tileset, camera));
+    }
+    /// Collision attribute bits of tile (x, y), merged over all collision layers
+    pub fn collision_bits(&self, x: usize, y: usize) -> u8 {
+        self.layers
+            .iter()
+            .filter(|l| l.collision)
+            .filter_map(|l| l.get(x, y))
+            .filter_map(|id| self.tileset.attr(id))
+            .fold(0, |bits, attr| bits | attr.collision)
+    }
+    /// Test `mesh` placed at `offset`(world coordinates) against solid tiles,
+    /// between layers allowed by `matrix`.
+    pub fn collide_mesh(
+        &self,
+        mesh: &MeshTree,
+        offset: DotPoint,
+        matrix: &LayerMatrix,
+    ) -> Option<TileContact> {
+        let bbox = mesh.bbox().translate(&offset.to_vector());
+        let len = TILE_SIZE as i16;
+        let mut res: Option<TileContact> = None;
+        for layer in self.layers.iter().filter(|l| l.collision) {
+            let range = match layer.tile_range(bbox) {
+                Some(r) => r,
+                None => continue,
+            };
+            for (x, y) in range {
+                let id = match layer.get(x, y) {
+                    Some(id) => id,
+                    None => continue,
+                };
+                let entry = match self.tileset.tiles.get(usize::from(id)) {
+                    Some(e) => e,
+                    None => continue,
+                };
+                let tile_mesh = match entry.mesh {
+                    Some(ref m) => m,
+                    None => continue,
+                };
+                let tile_origin = point2(x as i16 * len, y as i16 * len);
+                let region = match mesh.collide_with(tile_mesh, offset, tile_origin, matrix) {
+                    Some(c) => c.rect,
+                    None => continue,
+                };
+                let tile = (tile_mesh, tile_origin);
+                let (rect, pairs) = match overlapping_dots(mesh, offset, tile, region, matrix) {
+                    Some(dots) => dots,
+                    None => continue,
+                };
+                let contact = TileContact {
+                    rect: rect,
+                    penetration: penetration(bbox, rect),
+                    bits: pairs.bits_o(),
+                };
+                res = Some(res.map_or(contact, |r| r.merge(contact)));
+            }
+        }
+        res
+    }
+}
+
+/// Bounding rect of dots in `region` where `mesh`(placed at `offset`) hits `tile`
+/// (mesh and its origin), and the layer pairs which hit
+fn overlapping_dots(
+    mesh: &MeshTree,
+    offset: DotPoint,
+    tile: (&MeshTree, DotPoint),
+    region: DotRect,
+    matrix: &LayerMatrix,
+) -> Option<(DotRect, LayerPairs)> {
+    let (tile_mesh, tile_origin) = tile;
+    let mut res: Option<(DotRect, LayerPairs)> = None;
+    for (x, y) in RectRange::from_rect(region)? {
+        let p = point2(x, y);
+        let bits_s = mesh.bits_at(p - offset.to_vector());
+        let bits_o = tile_mesh.bits_at(p - tile_origin.to_vector());
+        let pairs = matrix.hits(u64::from(bits_s), u64::from(bits_o));
+        if pairs.is_empty() {
+            continue;
+        }
+        let dot = DotRect::new(p, size2(1, 1));
+        res = Some(match res {
+            Some((r, ps)) => (r.union(&dot), ps | pairs),
+            None => (dot, pairs),
+        });
+    }
+    res
+}
+
+/// Vector which pushes the object(with `bbox`) out of `contact`(overlapping dots)
+/// in the shallower axis. The other axis is 0.
+fn penetration(bbox: DotRect, contact: DotRect) -> DotVector {
+    let axis = |b_min: i16, b_max: i16, c_min: i16, c_max: i16| {
+        let depth = c_max - c_min;
+        match (c_min <= b_min, c_max >= b_max) {
+            // moving in this axis can't resolve the contact
+            (true, true) => 0,
+            (true, false) => depth,
+            (false, true) => -depth,
+            // the contact is inside the object, so push to the nearer side
+            (false, false) => {
+                if b_min + b_max < c_min + c_max {
+                    -depth
+                } else {
+                    depth
+                }
+            }
+        }
+    };
+    let x = axis(bbox.min_x(), bbox.max_x(), contact.min_x(), contact.max_x());
+    let y = axis(bbox.min_y(), bbox.max_y(), contact.min_y(), contact.max_y());
+    match (x, y) {
+        (0, _) | (_, 0) => vec2(x, y),
+        _ if x.abs() <= y.abs() => vec2(x, 0),
+        _ => vec2(0, y),
+    }
+}
+
+#[cfg(test)]
+mod tilemap_test {
+    use super::*;
+    use euclid::rect;
+    use image::Rgba;
+    use rect_iter::Get2D;
+    use testutils::{load_img, load_mesh};
+    #[test]
+    fn tileset_from_buf() {
+        let img = load_img("../test-assets/chara2.png");
+        let tileset = Tileset::from_buf(&img).unwrap();
+        assert_eq!(tileset.len(), 4);
+        let tile = tileset.get(1).unwrap();
+        let p = img.get_pixel(TILE_SIZE as u32 + 3, 5);
+        assert_eq!(*tile.get_xy(3, 5).unwrap(), Color::from_rgba(p));
+        assert!((0..4).any(|id| tileset.attr(id).unwrap().collision != 0));
+    }
+    #[test]
+    fn draw_with_camera() {
+        let img = load_img("../test-assets/chara2.png");
+        let tileset = Tileset::from_buf(&img).unwrap();
+        let attr = tileset.attr(3).unwrap();
+        let mut layer = TileLayer::new("ground", 40, 30).unwrap();
+        layer.set(20, 10, Some(3));
+        let mut map = TileMap::new(tileset);
+        map.push_layer(layer);
+        let mut camera = Camera::new();
+        camera.move_to(point2(300, 150));
+        let white = Rgba { data: [255; 4] };
+        let mut screen = RgbaImage::from_pixel(DOT_WIDTH as u32, DOT_HEIGHT as u32, white);
+        map.draw(&mut screen, &camera);
+        let tile = map.tileset().get(3).unwrap();
+        tile_rect().into_iter().for_each(|(x, y)| {
+            let mut expected = white;
+            if let Some(c) = *tile.get_xy(x, y).unwrap() {
+                expected.blend(c, attr.alpha);
+            }
+            let (sx, sy) = (20 * 16 - 300 + x as u32, 10 * 16 - 150 + y as u32);
+            assert_eq!(*screen.get_pixel(sx, sy), expected);
+        });
+        assert_eq!(map.collision_bits(20, 10), attr.collision);
+        assert_eq!(map.collision_bits(0, 0), 0);
+    }
+    #[test]
+    fn too_large() {
+        assert!(TileLayer::new("wide", MAX_LAYER_LEN, 1).is_ok());
+        match TileLayer::new("wide", MAX_LAYER_LEN + 1, 1) {
+            Err(TilemapError::LayerSize(2049, 1)) => {}
+            x => panic!("expected layer size error, but got {:?}", x),
+        }
+        assert!(check_tile_count(65536).is_ok());
+        assert_eq!(check_tile_count(65537), Err(TilemapError::TileCount(65537)));
+        // 257×256 tiles
+        assert!(Tileset::from_buf(&RgbaImage::new(16 * 257, 16 * 256)).is_none());
+    }
+    #[test]
+    fn draw_far_layer() {
+        let img = load_img("../test-assets/chara2.png");
+        let tileset = Tileset::from_buf(&img).unwrap();
+        let mut layer = TileLayer::new("ground", MAX_LAYER_LEN, 1).unwrap();
+        layer.set(MAX_LAYER_LEN - 1, 0, Some(3));
+        let mut map = TileMap::new(tileset);
+        map.push_layer(layer);
+        // the right edge of the view is i16::MAX
+        let mut camera = Camera::new();
+        camera.move_to(point2(i16::max_value() - DOT_WIDTH as i16, 0));
+        let white = Rgba { data: [255; 4] };
+        let mut screen = RgbaImage::from_pixel(DOT_WIDTH as u32, DOT_HEIGHT as u32, white);
+        map.draw(&mut screen, &camera);
+        let attr = map.tileset().attr(3).unwrap();
+        let tile = map.tileset().get(3).unwrap();
+        // the last column of the tile is out of the screen
+        tile_rect().into_iter().filter(|&(x, _)| x < 15).for_each(|(x, y)| {
+            let mut expected = white;
+            if let Some(c) = *tile.get_xy(x, y).unwrap() {
+                expected.blend(c, attr.alpha);
+            }
+            let sx = DOT_WIDTH as u32 - 15 + x as u32;
+            assert_eq!(*screen.get_pixel(sx, y as u32), expected);
+        });
+    }
+    #[test]
+    fn collide_with_solid_tiles() {
+        let bullet = load_mesh("../test-assets/bullet.png");
+        let chara1 = load_mesh("../test-assets/chara1.png");
+        let mut tileset = Tileset::new();
+        let wall = tileset.push(
+            Tile::new(Some(Color::black())),
+            TileAttr {
+                alpha: Alpha(15),
+                collision: 0b00001111,
+            },
+        ).unwrap();
+        let mut layer = TileLayer::new("wall", 10, 10).unwrap();
+        layer.set(3, 2, Some(wall));
+        let mut map = TileMap::new(tileset);
+        map.push_layer(layer);
+        let matrix = LayerMatrix::default();
+        // bullet's dot is (7, 8) in its tile
+        assert_eq!(map.collide_mesh(&bullet, point2(0, 0), &matrix), None);
+        let contact = map.collide_mesh(&bullet, point2(48 - 7, 32 - 8), &matrix).unwrap();
+        assert_eq!(contact.rect, rect(48, 32, 1, 1));
+        assert_eq!(contact.bits, 0b00001111);
+        // chara1's bbox is (2, 2, 11, 14), and its right side touches the wall
+        let contact = map.collide_mesh(&chara1, point2(40, 30), &matrix).unwrap();
+        assert_eq!(contact.rect, rect(48, 32, 5, 14));
+        assert_eq!(contact.penetration, vec2(-5, 0));
+        // the bottom 3 rows are in the wall, and only the dots there count
+        let contact = map.collide_mesh(&chara1, point2(46, 19), &matrix).unwrap();
+        assert_eq!(contact.rect, rect(50, 32, 8, 3));
+        assert_eq!(contact.penetration, vec2(0, -3));
+    }
+    #[test]
+    fn collide_floor_and_wall() {
+        let chara1 = load_mesh("../test-assets/chara1.png");
+        let mut tileset = Tileset::new();
+        let wall = tileset.push(
+            Tile::new(Some(Color::black())),
+            TileAttr {
+                alpha: Alpha(15),
+                collision: 0b00001111,
+            },
+        ).unwrap();
+        let mut layer = TileLayer::new("wall", 10, 10).unwrap();
+        layer.set(3, 2, Some(wall));
+        layer.set(2, 3, Some(wall));
+        let mut map = TileMap::new(tileset);
+        map.push_layer(layer);
+        let matrix = LayerMatrix::default();
+        // chara1's right side is in the wall(x >= 48), and its feet are in the
+        // floor(y >= 48)
+        let contact = map.collide_mesh(&chara1, point2(40, 35), &matrix).unwrap();
+        assert_eq!(contact.rect, rect(44, 37, 9, 14));
+        assert_eq!(contact.penetration, vec2(-5, -3));
+        assert_eq!(contact.bits, 0b00001111);
+    }
+    #[test]
+    fn collide_touched_bits() {
+        let bullet = load_mesh("../test-assets/bullet.png");
+        // left half of the tile has bit 1, and right half has bit 2
+        let img = RgbaImage::from_fn(16, 16, |x, _| Rgba {
+            data: [0, 0, 0, if x < 8 { 0xf1 } else { 0xf2 }],
+        });
+        let tileset = Tileset::from_buf(&img).unwrap();
+        assert_eq!(tileset.attr(0).unwrap().collision, 0b11);
+        let mut layer = TileLayer::new("wall", 4, 4).unwrap();
+        layer.set(1, 1, Some(0));
+        let mut map = TileMap::new(tileset);
+        map.push_layer(layer);
+        let matrix = LayerMatrix::default();
+        // bullet's dot is (7, 8) in its tile
+        let contact = map.collide_mesh(&bullet, point2(16 + 2 - 7, 16), &matrix).unwrap();
+        assert_eq!(contact.rect, rect(18, 24, 1, 1));
+        assert_eq!(contact.bits, 0b01);
+        let contact = map.collide_mesh(&bullet, point2(16 + 12 - 7, 16), &matrix).unwrap();
+        assert_eq!(contact.bits, 0b10);
+    }
+}